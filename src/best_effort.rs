@@ -0,0 +1,345 @@
+use crate::error::ScraperError;
+use crate::manifest::VolumeManifest;
+use crate::output_profile::OutputProfile;
+use crate::page::Page;
+use crate::page_size::PageSize;
+use crate::scraper::scraper_trait::Scraper;
+use crate::scraper::util::placeholder_page;
+use crate::trace_event;
+use futures_util::FutureExt;
+use getset::{CopyGetters, Getters};
+use lopdf::Document;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::fmt::Display;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::time::Duration;
+
+/// How often a failing page is tried again before it is replaced,
+/// see `Scraper::write_pages_best_effort`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct RetryPolicy {
+    retries: u32,
+    /// Delay before the first retry, doubled for every further one.
+    delay: Duration,
+}
+
+impl RetryPolicy {
+    pub const fn new(retries: u32, delay: Duration) -> Self {
+        Self { retries, delay }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3, Duration::from_secs(1))
+    }
+}
+
+/// What a failed page was replaced with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageReplacement {
+    /// A lower quality version of the page, see `Scraper::fetch_fallback_page`.
+    Fallback,
+    /// A page stating that and why the page is missing.
+    Placeholder,
+}
+
+impl Display for PageReplacement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PageReplacement::Fallback => "fallback",
+            PageReplacement::Placeholder => "placeholder",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct PageFailure {
+    /// One-based number of the page within the volume.
+    #[getset(get_copy = "pub")]
+    page: u16,
+    #[getset(get_copy = "pub")]
+    attempts: u32,
+    /// The error of the last attempt.
+    #[getset(get = "pub")]
+    error: String,
+    #[getset(get_copy = "pub")]
+    replacement: PageReplacement,
+}
+
+/// Summary of a best-effort download.
+#[derive(Debug, Clone, Default, Getters, CopyGetters)]
+pub struct DownloadReport {
+    /// Including the replaced pages.
+    #[getset(get_copy = "pub")]
+    page_count: u16,
+    #[getset(get = "pub")]
+    failures: Vec<PageFailure>,
+}
+
+impl DownloadReport {
+    /// Summarizes the pages written for `volumes`.
+    pub(crate) fn for_volumes(volumes: &[VolumeManifest]) -> Self {
+        Self {
+            page_count: volumes.iter().map(|volume| volume.page_count()).sum(),
+            failures: volumes
+                .iter()
+                .flat_map(|volume| volume.pages())
+                .filter_map(|page| page.failure().clone())
+                .collect(),
+        }
+    }
+
+    /// Whether no page had to be replaced.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for DownloadReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} pages, {} replaced",
+            self.page_count,
+            self.failures.len()
+        )?;
+
+        for failure in &self.failures {
+            writeln!(
+                f,
+                "- page {} ({} after {} attempts): {}",
+                failure.page, failure.replacement, failure.attempts, failure.error
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fetches a page like `Scraper::fetch_page`, but never fails.
+/// Transient request errors (see `is_transient`) are retried according to `policy`,
+/// then the page is replaced by the fallback of the scraper or a placeholder.
+/// Other errors and panics would fail again, so they replace the page right away.
+pub(crate) async fn fetch_page<S: Scraper + ?Sized>(
+    scraper: &S,
+    page: u16,
+    profile: &OutputProfile,
+    policy: &RetryPolicy,
+) -> (Page, Option<PageFailure>) {
+    let mut attempts = 0;
+    let mut delay = policy.delay;
+
+    let error = loop {
+        attempts += 1;
+
+        let (error, transient) = match catch_panic(scraper.fetch_page(page, profile)).await {
+            Ok(Ok(fetched)) => return (fetched, None),
+            Ok(Err(e)) => (e.to_string(), is_transient(&e)),
+            Err(panic) => (panic, false),
+        };
+
+        if !transient || attempts > policy.retries {
+            break error;
+        }
+
+        trace_event!(WARN, page, attempts, error = %error, "page failed, retrying");
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+    };
+
+    trace_event!(ERROR, page, attempts, error = %error, "page failed, replacing it");
+    let (document, replacement) =
+        match catch_panic(scraper.fetch_fallback_page(page, profile)).await {
+            Ok(Ok(Some(document))) => (document, PageReplacement::Fallback),
+            _ => (
+                placeholder(scraper, page, profile, &error).await,
+                PageReplacement::Placeholder,
+            ),
+        };
+
+    let failure = PageFailure {
+        page,
+        attempts,
+        error,
+        replacement,
+    };
+    let replaced = Page::new(page, scraper.get_page_label(page), document)
+        .with_source_url(scraper.get_page_url(page));
+    (replaced, Some(failure))
+}
+
+/// Whether the request might succeed when sent again,
+/// i.e. it timed out, the connection failed or the server is (temporarily) unable to answer.
+fn is_transient(error: &ScraperError) -> bool {
+    let ScraperError::Request(error) = error else {
+        return false;
+    };
+
+    match error.status() {
+        Some(status) => status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        None => error.is_timeout() || error.is_connect() || error.is_request() || error.is_body(),
+    }
+}
+
+/// A placeholder of the size of the page, if it can still be determined.
+async fn placeholder<S: Scraper + ?Sized>(
+    scraper: &S,
+    page: u16,
+    profile: &OutputProfile,
+    error: &str,
+) -> Document {
+    let size = match catch_panic(scraper.fetch_page_size(page)).await {
+        Ok(Ok(size)) => size,
+        _ => PageSize::A4,
+    };
+
+    let mut document = placeholder_page(
+        size,
+        &[
+            format!("Page {page} could not be downloaded."),
+            String::new(),
+            error.to_string(),
+        ],
+    );
+    profile.apply_paper_size(&mut document);
+    document
+}
+
+/// Scrapers still panic on unexpected content, which shouldn't end a best-effort download.
+async fn catch_panic<T>(future: impl Future<Output = T>) -> Result<T, String> {
+    AssertUnwindSafe(future)
+        .catch_unwind()
+        .await
+        .map_err(|panic| panic_message(&*panic))
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (_, Some(message)) => message.clone(),
+        _ => "the scraper panicked".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffered_response::BufferedResponse;
+    use crate::http::HttpClient;
+    use crate::scraper::BaseScraper;
+    use async_trait::async_trait;
+    use std::sync::Arc;
+
+    /// Answers every page with `status`.
+    #[derive(Debug)]
+    struct FailingScraper {
+        status: StatusCode,
+        page_count: u16,
+    }
+
+    #[async_trait]
+    impl BaseScraper for FailingScraper {
+        fn new_scraper(
+            _resp: Arc<BufferedResponse>,
+            _client: Arc<HttpClient>,
+        ) -> Result<Box<dyn Scraper>, ScraperError> {
+            unreachable!()
+        }
+
+        async fn fetch_page_count(&self) -> Result<u16, ScraperError> {
+            Ok(self.page_count)
+        }
+    }
+
+    #[async_trait]
+    impl Scraper for FailingScraper {
+        async fn fetch_page_raw_pdf(
+            &self,
+            _page: u16,
+            _profile: &OutputProfile,
+        ) -> Result<Vec<u8>, ScraperError> {
+            Err(status_error(self.status).into())
+        }
+
+        async fn fetch_page_source(&self, _page: u16) -> Result<Vec<u8>, ScraperError> {
+            Err(status_error(self.status).into())
+        }
+
+        async fn fetch_page_size(&self, _page: u16) -> Result<PageSize, ScraperError> {
+            Ok(PageSize::A4)
+        }
+    }
+
+    fn status_error(status: StatusCode) -> reqwest::Error {
+        let resp = http::Response::builder().status(status).body("").unwrap();
+        reqwest::Response::from(resp)
+            .error_for_status()
+            .unwrap_err()
+    }
+
+    async fn failure(status: StatusCode) -> PageFailure {
+        let policy = RetryPolicy::new(2, Duration::from_millis(1));
+        let (page, failure) = fetch_page(
+            &FailingScraper {
+                status,
+                page_count: 1,
+            },
+            1,
+            &OutputProfile::default(),
+            &policy,
+        )
+        .await;
+        assert_eq!(page.pdf().get_pages().len(), 1);
+        failure.unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_transient_errors() {
+        let failure = failure(StatusCode::SERVICE_UNAVAILABLE).await;
+        assert_eq!(failure.attempts(), 3);
+        assert_eq!(failure.replacement(), PageReplacement::Placeholder);
+    }
+
+    #[tokio::test]
+    async fn replaces_pages_with_permanent_errors_right_away() {
+        let failure = failure(StatusCode::NOT_FOUND).await;
+        assert_eq!(failure.attempts(), 1);
+        assert_eq!(failure.replacement(), PageReplacement::Placeholder);
+    }
+
+    #[tokio::test]
+    async fn volume_without_pages_is_empty() {
+        let scraper = FailingScraper {
+            status: StatusCode::NOT_FOUND,
+            page_count: 0,
+        };
+
+        let (document, report) = scraper
+            .download_book_best_effort(&OutputProfile::default(), &RetryPolicy::default())
+            .await
+            .unwrap();
+        assert!(document.get_pages().is_empty());
+        assert!(report.is_complete());
+
+        let document = scraper.download_book().await.unwrap();
+        assert!(document.get_pages().is_empty());
+    }
+
+    #[test]
+    fn only_request_errors_are_transient() {
+        assert!(is_transient(
+            &status_error(StatusCode::TOO_MANY_REQUESTS).into()
+        ));
+        assert!(is_transient(&status_error(StatusCode::BAD_GATEWAY).into()));
+        assert!(!is_transient(&status_error(StatusCode::FORBIDDEN).into()));
+        assert!(!is_transient(&ScraperError::PdfError(
+            lopdf::Error::PageNumberNotFound(1)
+        )));
+    }
+}
@@ -0,0 +1,544 @@
+use clap::{Args, Parser, Subcommand};
+use digi_download_core::best_effort::RetryPolicy;
+use digi_download_core::digi4school::book::Book;
+use digi_download_core::digi4school::credentials::{Credentials, PromptCredentials};
+use digi_download_core::digi4school::session::Session;
+use digi_download_core::digi4school::volume::Volume;
+use digi_download_core::error::{DigiDownloadError, ScraperError};
+use digi_download_core::export::{export_library, ExportOptions};
+use digi_download_core::http::har::HarRecorder;
+use digi_download_core::http::replay::Replay;
+use digi_download_core::manifest::{verify, Manifest, VolumeManifest};
+use digi_download_core::output_profile::OutputProfile;
+use digi_download_core::page_size::PageSize;
+use digi_download_core::{sanitize_file_name, PdfWriter};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::{AsyncWrite, BufWriter};
+
+/// Downloads the books of a digi4school account as PDFs.
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    #[command(flatten)]
+    credentials: CredentialArgs,
+
+    /// Records all requests into an HTTP Archive (credentials redacted), e.g. to attach it to a bug report.
+    #[arg(long, global = true, value_name = "PATH")]
+    record_har: Option<PathBuf>,
+
+    /// Answers all requests from a recorded HTTP Archive (or fixture directory) instead of the portal,
+    /// no credentials are needed.
+    #[arg(
+        long,
+        global = true,
+        value_name = "PATH",
+        conflicts_with = "record_har"
+    )]
+    replay: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args)]
+struct CredentialArgs {
+    /// Prompted for if not given.
+    #[arg(long, env = "DIGI4SCHOOL_EMAIL", global = true)]
+    email: Option<String>,
+
+    /// Prompted for if not given.
+    #[arg(
+        long,
+        env = "DIGI4SCHOOL_PASSWORD",
+        hide_env_values = true,
+        global = true
+    )]
+    password: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Checks the credentials.
+    Login,
+
+    /// Lists the books of the account.
+    Books,
+
+    /// Lists the volumes of a book.
+    Volumes {
+        /// Id or (part of the) title of the book.
+        book: String,
+    },
+
+    /// Downloads a book or one of its volumes.
+    Download {
+        /// Id or (part of the) title of the book.
+        book: String,
+
+        /// Number of the volume (see `volumes`), all volumes are downloaded into one file if not given.
+        #[arg(long)]
+        volume: Option<usize>,
+
+        /// Pages of the volume to download, e.g. `5-20`, `5-` or `7`.
+        #[arg(long, requires = "volume", value_parser = parse_page_range)]
+        pages: Option<RangeInclusive<u16>>,
+
+        /// Defaults to the title of the book (or volume) in the current directory.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Number of pages downloaded at once.
+        #[arg(short, long, default_value_t = 4)]
+        concurrency: usize,
+
+        /// Replaces failing pages with a placeholder instead of aborting, after retrying timeouts and server errors.
+        /// Exits with code 2 if any page was replaced.
+        #[arg(long)]
+        best_effort: bool,
+
+        #[command(flatten)]
+        profile: ProfileArgs,
+    },
+
+    /// Downloads every book of the account into a directory.
+    ExportAll {
+        dir: PathBuf,
+
+        /// See `ExportOptions::template`.
+        #[arg(long, default_value = ExportOptions::DEFAULT_TEMPLATE)]
+        template: String,
+
+        /// Downloads volumes again even if their file exists.
+        #[arg(long)]
+        overwrite: bool,
+
+        #[command(flatten)]
+        profile: ProfileArgs,
+    },
+
+    /// Checks a downloaded file against the manifest saved next to it.
+    Verify { file: PathBuf },
+}
+
+#[derive(Args)]
+struct ProfileArgs {
+    /// `screen`, `print` or `archive`.
+    #[arg(long, default_value = "archive")]
+    profile: OutputProfile,
+
+    /// Converts all images to grayscale.
+    #[arg(long)]
+    grayscale: bool,
+
+    /// Scales every page to a paper size, e.g. `a4`, `letter` or `210mmx297mm`.
+    #[arg(long)]
+    paper_size: Option<PageSize>,
+}
+
+impl ProfileArgs {
+    fn profile(&self) -> OutputProfile {
+        self.profile
+            .with_grayscale(self.grayscale)
+            .with_paper_size(self.paper_size)
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let recorder = cli
+        .record_har
+        .as_ref()
+        .map(|_| Arc::new(HarRecorder::new()));
+
+    let connection = Connection {
+        credentials: cli.credentials,
+        recorder: recorder.clone(),
+        replay: cli.replay,
+    };
+
+    let code = match run(cli.command, connection).await {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    };
+
+    // failed runs are the interesting ones, so the traffic is saved either way
+    if let (Some(path), Some(recorder)) = (&cli.record_har, recorder) {
+        if let Err(e) = recorder.save(path).await {
+            eprintln!("error: failed to save {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+        eprintln!("Recorded {} requests to {}", recorder.len(), path.display());
+    }
+
+    code
+}
+
+/// How the session is established.
+struct Connection {
+    credentials: CredentialArgs,
+    recorder: Option<Arc<HarRecorder>>,
+    replay: Option<PathBuf>,
+}
+
+async fn run(command: Command, connection: Connection) -> Result<ExitCode, DigiDownloadError> {
+    // doesn't need the portal
+    if let Command::Verify { file } = &command {
+        let verification = verify(file).await?;
+        print!("{verification}");
+
+        return Ok(match verification.is_ok() {
+            true => ExitCode::SUCCESS,
+            false => ExitCode::FAILURE,
+        });
+    }
+
+    let session = login(connection).await?;
+
+    match command {
+        Command::Login => println!("Login successful"),
+        Command::Books => {
+            for book in session.get_books().await? {
+                println!("{}\t{}\t{}", book.id(), book.year(), book.title());
+            }
+        }
+        Command::Volumes { book } => {
+            let Some(book) = find_book(&session, &book).await? else {
+                return Ok(ExitCode::FAILURE);
+            };
+
+            for (i, volume) in book.get_volumes().await?.iter().enumerate() {
+                println!("{}\t{}", i + 1, volume.name());
+            }
+        }
+        Command::Download {
+            book,
+            volume,
+            pages,
+            output,
+            concurrency,
+            best_effort,
+            profile,
+        } => {
+            let Some(book) = find_book(&session, &book).await? else {
+                return Ok(ExitCode::FAILURE);
+            };
+
+            let options = DownloadOptions {
+                pages,
+                concurrency,
+                best_effort,
+                profile: profile.profile(),
+            };
+            return download(&book, volume, output, &options).await;
+        }
+        Command::ExportAll {
+            dir,
+            template,
+            overwrite,
+            profile,
+        } => {
+            let options = ExportOptions::default()
+                .with_template(template)
+                .with_profile(profile.profile())
+                .with_skip_existing(!overwrite);
+
+            let report = export_library(&session, &dir, &options).await?;
+            print!("{report}");
+
+            if !report.is_success() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Verify { .. } => unreachable!("handled before logging in"),
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn login(connection: Connection) -> Result<Session, DigiDownloadError> {
+    let Connection {
+        credentials: args,
+        recorder,
+        replay,
+    } = connection;
+
+    if let Some(path) = replay {
+        return Ok(Session::replay(Arc::new(Replay::load(&path).await?)).await?);
+    }
+
+    if let (Some(email), Some(password)) = (&args.email, &args.password) {
+        let credentials = Credentials::new(email.clone(), password.clone());
+        return Ok(match recorder {
+            Some(recorder) => Session::with_recorder(credentials, recorder).await?,
+            None => Session::with_credentials(credentials).await?,
+        });
+    }
+
+    // also asked again if the session expires during a long export
+    let prompt = PromptCredentials::new(move || {
+        let email = match &args.email {
+            Some(email) => email.clone(),
+            None => {
+                eprint!("Email: ");
+                std::io::stderr().flush().ok()?;
+
+                let mut email = String::new();
+                std::io::stdin().read_line(&mut email).ok()?;
+                email.trim().to_string()
+            }
+        };
+        let password = match &args.password {
+            Some(password) => password.clone(),
+            None => rpassword::prompt_password("Password: ").ok()?,
+        };
+
+        Some(Credentials::new(email, password))
+    });
+
+    Ok(match recorder {
+        Some(recorder) => Session::with_recorder(prompt, recorder).await?,
+        None => Session::with_credentials(prompt).await?,
+    })
+}
+
+/// Finds a book by its id or a part of its title.
+/// Prints why if there is no single match.
+async fn find_book(session: &Session, query: &str) -> Result<Option<Book>, DigiDownloadError> {
+    let books = session.get_books().await?;
+
+    if let Some(book) = books
+        .iter()
+        .find(|book| book.id().to_string() == query.trim())
+    {
+        return Ok(Some(book.clone()));
+    }
+
+    let query = query.to_lowercase();
+    let mut matches: Vec<Book> = books
+        .into_iter()
+        .filter(|book| book.title().to_lowercase().contains(&query))
+        .collect();
+
+    match matches.len() {
+        0 => eprintln!("error: no book matches '{query}', see `digidownload books`"),
+        1 => return Ok(matches.pop()),
+        _ => {
+            eprintln!("error: '{query}' matches multiple books, use the id of one of them:");
+            for book in matches {
+                eprintln!("{}\t{}", book.id(), book);
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+struct DownloadOptions {
+    pages: Option<RangeInclusive<u16>>,
+    concurrency: usize,
+    best_effort: bool,
+    profile: OutputProfile,
+}
+
+async fn download(
+    book: &Book,
+    volume: Option<usize>,
+    output: Option<PathBuf>,
+    options: &DownloadOptions,
+) -> Result<ExitCode, DigiDownloadError> {
+    let mut volumes = book.get_volumes().await?;
+
+    let volumes = match volume {
+        Some(index) if (1..=volumes.len()).contains(&index) => vec![volumes.swap_remove(index - 1)],
+        Some(index) => {
+            eprintln!(
+                "error: '{book}' has no volume {index}, it has {} volumes",
+                volumes.len()
+            );
+            return Ok(ExitCode::FAILURE);
+        }
+        None => volumes,
+    };
+
+    let output = output.unwrap_or_else(|| {
+        let name = match &volumes[..] {
+            [volume] => volume.name(),
+            _ => book.title(),
+        };
+        PathBuf::from(format!("{}.pdf", sanitize_file_name(name)))
+    });
+
+    let part_path = output.with_extension("pdf.part");
+    let result = write_volumes(&volumes, &part_path, options).await;
+    let manifests = match result {
+        Ok(manifests) if manifests.is_empty() => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            eprintln!("error: no pages were written, every volume ends before the requested pages");
+            return Ok(ExitCode::FAILURE);
+        }
+        Ok(manifests) => {
+            tokio::fs::rename(&part_path, &output).await?;
+            manifests
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e);
+        }
+    };
+
+    let manifest = Manifest::for_file(&output, manifests)
+        .await?
+        .with_book(book);
+    manifest.save(&output).await?;
+    eprintln!("Saved {}", output.display());
+
+    let replaced = manifest
+        .volumes()
+        .iter()
+        .flat_map(|volume| volume.pages())
+        .filter(|page| page.failure().is_some())
+        .count();
+    if replaced > 0 {
+        eprintln!("warning: {replaced} pages were replaced, see above");
+        return Ok(ExitCode::from(2));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn write_volumes(
+    volumes: &[Volume],
+    path: &Path,
+    options: &DownloadOptions,
+) -> Result<Vec<VolumeManifest>, DigiDownloadError> {
+    let mut manifests = Vec::new();
+    let mut file = BufWriter::new(File::create(path).await?);
+    let out: &mut (dyn AsyncWrite + Unpin + Send) = &mut file;
+    let mut writer = PdfWriter::new(out).await.map_err(ScraperError::from)?;
+
+    for (i, volume) in volumes.iter().enumerate() {
+        let scraper = volume.get_scraper().await?;
+        let page_count = scraper.fetch_page_count().await?;
+
+        let pages = match &options.pages {
+            Some(pages) => *pages.start()..=(*pages.end()).min(page_count),
+            None => 1..=page_count,
+        };
+        if pages.is_empty() {
+            eprintln!("{}: no pages in {pages:?}", volume.name());
+            continue;
+        }
+
+        // the volume bookmark is only useful if there are multiple
+        let title = (volumes.len() > 1).then(|| volume.name().clone());
+        let prefix = format!("[{}/{}] {}", i + 1, volumes.len(), volume.name());
+        let last_page = *pages.end();
+        let expected_page_count = last_page - *pages.start() + 1;
+
+        let progress = |page| eprint!("\r{prefix}: page {page}/{last_page}");
+
+        let written = match options.best_effort {
+            false => {
+                scraper
+                    .write_pages(
+                        &mut writer,
+                        &options.profile,
+                        title,
+                        pages,
+                        options.concurrency,
+                        &progress,
+                    )
+                    .await?
+            }
+            true => {
+                scraper
+                    .write_pages_best_effort(
+                        &mut writer,
+                        &options.profile,
+                        title,
+                        pages,
+                        options.concurrency,
+                        &RetryPolicy::default(),
+                        &progress,
+                    )
+                    .await?
+            }
+        };
+        eprintln!();
+
+        for failure in written.iter().filter_map(|page| page.failure().as_ref()) {
+            eprintln!(
+                "{}: page {} replaced by a {} after {} attempts: {}",
+                volume.name(),
+                failure.page(),
+                failure.replacement(),
+                failure.attempts(),
+                failure.error()
+            );
+        }
+        let degraded: Vec<u16> = written
+            .iter()
+            .filter(|page| page.degraded())
+            .map(|page| page.index())
+            .collect();
+        if !degraded.is_empty() {
+            eprintln!(
+                "{}: pages {degraded:?} are only available in a lower resolution",
+                volume.name()
+            );
+        }
+        manifests.push(VolumeManifest::new(expected_page_count, written).with_volume(volume));
+    }
+
+    writer.finish().await.map_err(ScraperError::from)?;
+    Ok(manifests)
+}
+
+fn parse_page_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |page: &str| {
+        page.trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|page| *page >= 1)
+            .ok_or_else(|| format!("'{page}' is not a page number"))
+    };
+
+    let (start, end) = match range.split_once('-') {
+        Some((start, "")) => (parse(start)?, u16::MAX),
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(range)?, parse(range)?),
+    };
+
+    if start > end {
+        return Err(format!("the range '{range}' is empty"));
+    }
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_page_ranges() {
+        assert_eq!(parse_page_range("5-20"), Ok(5..=20));
+        assert_eq!(parse_page_range(" 5 - 20 "), Ok(5..=20));
+        assert_eq!(parse_page_range("5-"), Ok(5..=u16::MAX));
+        assert_eq!(parse_page_range("7"), Ok(7..=7));
+    }
+
+    #[test]
+    fn rejects_invalid_page_ranges() {
+        for range in ["", "0", "0-5", "-5", "20-5", "a-b", "5-6-7"] {
+            assert!(parse_page_range(range).is_err(), "{range}");
+        }
+    }
+}
@@ -1,6 +1,11 @@
+use crate::{regex, trace_event};
+use bytes::Bytes;
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::header::CONTENT_TYPE;
 use reqwest::Response;
 use std::ops::Deref;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 
 /// Stores a response and keeps track of its body in one type.
 /// Circumvents consumption of Response by `.text()` or `.bytes()`
@@ -8,56 +13,153 @@ use std::sync::atomic::{AtomicBool, Ordering};
 #[derive(Debug)]
 pub struct BufferedResponse {
     resp: Response,
-    buf: Vec<u8>,
+    buf: Bytes,
 
-    utf8_check_passed: AtomicBool,
+    /// Decoded on the first call to `text`.
+    text: OnceLock<String>,
 }
 
 impl BufferedResponse {
-    pub async fn new(mut resp: Response) -> Result<Self, reqwest::Error> {
-        let buf = Self::get_buf(&mut resp).await?;
+    pub async fn new(resp: Response) -> Result<Self, reqwest::Error> {
+        Ok(Self::read_limited(resp, u64::MAX)
+            .await?
+            .expect("no body is larger than u64::MAX"))
+    }
+
+    /// Like `new`, but `None` if the body is larger than `max_size` bytes.
+    /// The rest of the body isn't downloaded then.
+    pub async fn with_max_size(
+        resp: Response,
+        max_size: u64,
+    ) -> Result<Option<Self>, reqwest::Error> {
+        Ok(Self::read_limited(resp, max_size).await?.ok())
+    }
 
-        Ok(Self {
+    /// Hands back the response if its body is too large, e.g. to record its headers.
+    pub(crate) async fn read_limited(
+        mut resp: Response,
+        max_size: u64,
+    ) -> Result<Result<Self, Response>, reqwest::Error> {
+        let Some(buf) = Self::get_buf(&mut resp, max_size).await? else {
+            trace_event!(DEBUG, url = %resp.url(), max_size, "response body too large");
+            return Ok(Err(resp));
+        };
+        trace_event!(
+            DEBUG,
+            status = resp.status().as_u16(),
+            url = %resp.url(),
+            bytes = buf.len(),
+            "received response"
+        );
+
+        Ok(Ok(Self {
             resp,
             buf,
 
-            utf8_check_passed: AtomicBool::new(false),
+            text: OnceLock::new(),
+        }))
+    }
+
+    /// The body decoded with the charset of the `Content-Type`,
+    /// or if there is none, the one declared by the document (`<meta charset>` or `<?xml encoding?>`),
+    /// or the one detected from the body (UTF-8 if it is valid UTF-8).
+    /// Invalid sequences are replaced with U+FFFD.
+    pub fn text(&self) -> &str {
+        self.text.get_or_init(|| {
+            // the encoding actually used can differ because of a byte order mark
+            let (text, _encoding, malformed) = self.encoding().decode(&self.buf);
+            if malformed {
+                trace_event!(
+                    WARN,
+                    url = %self.resp.url(),
+                    encoding = _encoding.name(),
+                    "body contains invalid characters"
+                );
+            }
+            text.into_owned()
         })
     }
 
-    pub fn text(&self) -> String {
-        if self.utf8_check_passed.load(Ordering::Acquire) {
-            unsafe { return String::from_utf8_unchecked(self.buf.clone()) }
+    pub fn bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// The body without copying it.
+    pub fn body(&self) -> Bytes {
+        self.buf.clone()
+    }
+
+    pub fn into_body(self) -> Bytes {
+        self.buf
+    }
+
+    /// A byte order mark overrides the returned encoding when decoding.
+    fn encoding(&self) -> &'static Encoding {
+        let declared = self
+            .resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .and_then(|content_type| {
+                content_type.split(';').skip(1).find_map(|parameter| {
+                    let (name, value) = parameter.split_once('=')?;
+                    name.trim()
+                        .eq_ignore_ascii_case("charset")
+                        .then(|| value.trim().trim_matches('"'))
+                })
+            })
+            .and_then(|charset| Encoding::for_label(charset.as_bytes()));
+
+        if let Some(encoding) = declared.or_else(|| self.document_encoding()) {
+            return encoding;
+        }
+        if std::str::from_utf8(&self.buf).is_ok() {
+            return UTF_8;
         }
 
-        let result = String::from_utf8(self.buf.clone()).expect("expected charset to be utf8");
-        self.utf8_check_passed.store(true, Ordering::Release);
-        result
+        let mut detector = EncodingDetector::new();
+        detector.feed(&self.buf, true);
+        detector.guess(None, false)
     }
 
-    pub fn bytes(&self) -> &[u8] {
-        &self.buf
+    /// The encoding declared at the start of an HTML or XML document.
+    fn document_encoding(&self) -> Option<&'static Encoding> {
+        // like browsers, only the start of the document is searched
+        let start = String::from_utf8_lossy(&self.buf[..self.buf.len().min(1024)]);
+        let declaration = regex!(
+            r#"(?i)<meta\s[^>]*?charset\s*=\s*["']?\s*([a-z0-9_.:-]+)|^\x{feff}?\s*<\?xml\s[^>]*?encoding\s*=\s*["']([a-z0-9_.:-]+)"#
+        )
+        .captures(&start)?;
+        let charset = declaration.get(1).or_else(|| declaration.get(2))?;
+
+        Encoding::for_label(charset.as_str().as_bytes())
     }
 
-    async fn get_buf(resp: &mut Response) -> Result<Vec<u8>, reqwest::Error> {
-        let content_length = resp.content_length();
+    /// `None` if the body is larger than `max_size`.
+    async fn get_buf(resp: &mut Response, max_size: u64) -> Result<Option<Bytes>, reqwest::Error> {
+        if resp
+            .content_length()
+            .is_some_and(|length| length > max_size)
+        {
+            return Ok(None);
+        }
 
-        let mut buf: Vec<u8>;
-        match content_length {
-            Some(length) => {
-                buf = Vec::with_capacity(length.try_into().unwrap());
+        let mut chunks = Vec::new();
+        let mut size = 0;
+        while let Some(chunk) = resp.chunk().await? {
+            size += chunk.len() as u64;
+            if size > max_size {
+                return Ok(None);
             }
-            None => buf = Vec::new(),
+            chunks.push(chunk);
         }
 
-        loop {
-            if let Some(chunk) = resp.chunk().await? {
-                buf.append(&mut chunk.to_vec());
-            } else {
-                buf.shrink_to_fit();
-                return Ok(buf);
-            };
-        }
+        // most bodies arrive in one chunk, which can be kept as is
+        Ok(Some(match chunks.len() {
+            0 => Bytes::new(),
+            1 => chunks.remove(0),
+            _ => chunks.concat().into(),
+        }))
     }
 }
 
@@ -68,3 +170,95 @@ impl Deref for BufferedResponse {
         &self.resp
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A response with the given `Content-Type` and body, as if it was received.
+    pub(crate) async fn response(content_type: &str, body: impl Into<Bytes>) -> BufferedResponse {
+        let resp = http::Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body(body.into())
+            .unwrap();
+        BufferedResponse::new(resp.into()).await.unwrap()
+    }
+
+    async fn text(content_type: &str, body: &'static [u8]) -> String {
+        response(content_type, body).await.text().to_string()
+    }
+
+    #[tokio::test]
+    async fn decodes_with_the_charset_of_the_content_type() {
+        assert_eq!(
+            text("text/html; charset=ISO-8859-1", b"Br\xfcche").await,
+            "Brüche"
+        );
+        assert_eq!(
+            text("text/html; boundary=x; Charset=\"windows-1252\"", b"\x80").await,
+            "€"
+        );
+        // the header wins over the document
+        assert_eq!(
+            text(
+                "text/html; charset=utf-8",
+                b"<meta charset=\"latin1\">\xc3\xbc"
+            )
+            .await,
+            "<meta charset=\"latin1\">ü"
+        );
+    }
+
+    #[tokio::test]
+    async fn decodes_with_the_charset_of_the_document() {
+        assert_eq!(
+            text(
+                "text/html",
+                b"<head><meta charset=\"windows-1252\"></head>\xfc"
+            )
+            .await,
+            "<head><meta charset=\"windows-1252\"></head>ü"
+        );
+        assert_eq!(
+            text(
+                "text/html",
+                b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-1\">\xfc"
+            )
+            .await,
+            "<meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-1\">ü"
+        );
+        assert_eq!(
+            text(
+                "image/svg+xml",
+                b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><svg>\xfc</svg>"
+            )
+            .await,
+            "<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><svg>ü</svg>"
+        );
+    }
+
+    #[tokio::test]
+    async fn detects_undeclared_charsets() {
+        assert_eq!(text("text/plain", "Brüche".as_bytes()).await, "Brüche");
+        assert_eq!(
+            text("text/plain", b"Gr\xfc\xdfe aus \xd6sterreich").await,
+            "Grüße aus Österreich"
+        );
+    }
+
+    #[tokio::test]
+    async fn stops_reading_bodies_larger_than_max_size() {
+        let resp = http::Response::builder().body("0123456789").unwrap();
+        assert!(BufferedResponse::with_max_size(resp.into(), 9)
+            .await
+            .unwrap()
+            .is_none());
+
+        let resp = http::Response::builder().body("0123456789").unwrap();
+        let buffered = BufferedResponse::with_max_size(resp.into(), 10)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(buffered.bytes(), b"0123456789");
+    }
+}
@@ -0,0 +1,189 @@
+use crate::buffered_response::BufferedResponse;
+use crate::regex;
+use crate::util::sanitize_file_name;
+use getset::{CopyGetters, Getters};
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttachmentKind {
+    Audio,
+    Video,
+    /// Worksheets, solutions, ...
+    Document,
+    Other,
+}
+
+impl AttachmentKind {
+    fn from_extension(extension: &str) -> Option<Self> {
+        Some(match extension.to_lowercase().as_str() {
+            "mp3" | "wav" | "ogg" | "m4a" | "aac" | "flac" => Self::Audio,
+            "mp4" | "webm" | "mov" | "m4v" | "avi" => Self::Video,
+            "pdf" | "doc" | "docx" | "odt" | "xls" | "xlsx" | "ods" | "ppt" | "pptx" | "odp"
+            | "txt" | "rtf" => Self::Document,
+            "zip" | "ggb" | "sb3" => Self::Other,
+            _ => return None,
+        })
+    }
+}
+
+/// Extra material linked on the landing page of a volume (audio tracks, videos, worksheets, ...).
+#[derive(Debug, Clone, Getters, CopyGetters)]
+pub struct Attachment {
+    #[getset(get_copy = "pub")]
+    kind: AttachmentKind,
+    #[getset(get = "pub")]
+    title: String,
+    #[getset(get = "pub")]
+    url: Url,
+    /// The (one-based) page the attachment belongs to, if the volume specifies it.
+    #[getset(get_copy = "pub")]
+    page: Option<u16>,
+}
+
+impl Attachment {
+    /// Name of the file on the server.
+    pub fn file_name(&self) -> &str {
+        self.url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or_default()
+    }
+
+    /// The sanitized `file_name` prefixed with the position of the attachment in its volume,
+    /// because attachments of different pages often share their file name.
+    pub(crate) fn numbered_file_name(&self, index: usize) -> String {
+        format!("{:02} {}", index + 1, sanitize_file_name(self.file_name()))
+    }
+
+    /// Collects every link to a file on the landing page of a volume.
+    ///
+    /// PDFs are only attachments if their title marks them as supplementary material,
+    /// other PDFs are the volume itself (see `NativePdfScraper`).
+    pub(crate) fn from_response(resp: &BufferedResponse) -> Vec<Self> {
+        let doc = Html::parse_document(resp.text());
+        let selector = Selector::parse("a[href]").unwrap();
+
+        let mut attachments: Vec<Self> = Vec::new();
+        for link in doc.root_element().select(&selector) {
+            let Ok(url) = resp.url().join(link.attr("href").unwrap()) else {
+                continue;
+            };
+
+            let Some(kind) = url
+                .path()
+                .rsplit_once('.')
+                .and_then(|(_, extension)| AttachmentKind::from_extension(extension))
+            else {
+                continue;
+            };
+
+            let title = link.text().collect::<String>().trim().to_string();
+            if url.path().to_lowercase().ends_with(".pdf") && !Self::is_material_title(&title) {
+                continue;
+            }
+
+            // the same file is often linked by its icon and its title
+            if let Some(attachment) = attachments.iter_mut().find(|a| a.url == url) {
+                if attachment.title.is_empty() {
+                    attachment.title = title;
+                }
+                continue;
+            }
+
+            attachments.push(Self {
+                kind,
+                page: Self::get_page(link, &title),
+                title,
+                url,
+            });
+        }
+
+        for attachment in &mut attachments {
+            if attachment.title.is_empty() {
+                attachment.title = attachment.file_name().to_string();
+            }
+        }
+
+        attachments
+    }
+
+    pub(crate) fn is_material_title(title: &str) -> bool {
+        regex!(r"(?i)l(ö|oe)sung|arbeitsblatt|worksheet|solution|material|beilage|kopiervorlage")
+            .is_match(title)
+    }
+
+    /// Reads a `data-page` attribute of the link or one of its parents, or a page mentioned in the title.
+    fn get_page(link: ElementRef, title: &str) -> Option<u16> {
+        let from_attribute = std::iter::once(link)
+            .chain(link.ancestors().filter_map(ElementRef::wrap))
+            .find_map(|element| element.attr("data-page")?.trim().parse().ok());
+
+        from_attribute.or_else(|| {
+            regex!(r"(?i)\b(?:seite|s\.)\s*(\d+)")
+                .captures(title)?
+                .get(1)?
+                .as_str()
+                .parse()
+                .ok()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffered_response::tests::response;
+
+    #[tokio::test]
+    async fn collects_material_links() {
+        let resp = response(
+            "text/html",
+            r#"<div data-page="12"><a href="media/track1.mp3"><img src="icon.png"></a>
+            <a href="media/track1.mp3">Track 1</a></div>
+            <a href="media/loesungen.pdf">Lösungen Seite 40</a>
+            <a href="band.pdf">Band</a>
+            <a href="index.html">Start</a>"#,
+        )
+        .await;
+
+        let attachments: Vec<_> = Attachment::from_response(&resp)
+            .into_iter()
+            .map(|a| (a.kind(), a.file_name().to_string(), a.page()))
+            .collect();
+        assert_eq!(
+            attachments,
+            [
+                (AttachmentKind::Audio, "track1.mp3".to_string(), Some(12)),
+                (
+                    AttachmentKind::Document,
+                    "loesungen.pdf".to_string(),
+                    Some(40)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbered_file_names_are_unique_and_safe() {
+        let attachment = |url: &str| Attachment {
+            kind: AttachmentKind::Audio,
+            title: String::new(),
+            url: Url::parse(url).unwrap(),
+            page: None,
+        };
+
+        assert_eq!(
+            attachment("https://a.digi4school.at/s1/track.mp3").numbered_file_name(0),
+            "01 track.mp3"
+        );
+        assert_eq!(
+            attachment("https://a.digi4school.at/s2/track.mp3").numbered_file_name(1),
+            "02 track.mp3"
+        );
+        assert_eq!(
+            attachment("https://a.digi4school.at/s2/a:b.mp3").numbered_file_name(2),
+            "03 a_b.mp3"
+        );
+    }
+}
@@ -1,17 +1,26 @@
-use crate::buffered_response::BufferedResponse;
+use crate::best_effort::{DownloadReport, RetryPolicy};
 use crate::digi4school::lti_form::LTIForm;
 use crate::digi4school::volume::Volume;
-use crate::regex_builder;
+use crate::error::{DigiDownloadError, ScraperError};
+use crate::http::HttpClient;
+use crate::manifest::{Manifest, VolumeManifest};
+use crate::output_profile::OutputProfile;
+use crate::scraper::PdfWriter;
+use crate::util::sanitize_file_name;
+use crate::{regex_builder, trace_event};
 use getset::{CopyGetters, Getters};
 use regex::RegexBuilder;
-use reqwest::{Client, Url};
+use reqwest::Url;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use tokio::fs::File;
+use tokio::io::{AsyncWrite, BufWriter};
 
 #[derive(Debug, Clone, Getters, CopyGetters)]
 pub struct Book {
-    #[getset(get_copy = "pub(crate)")]
+    #[getset(get_copy = "pub")]
     id: u16,
 
     /// Keeps track of the date at which the book was obtained.
@@ -23,7 +32,7 @@ pub struct Book {
     #[getset(get = "pub")]
     thumbnail: Url,
 
-    client: Arc<Client>,
+    client: Arc<HttpClient>,
 }
 
 impl Book {
@@ -34,7 +43,7 @@ impl Book {
         expiration_year: u16,
         thumbnail: Url,
         name: &str,
-        client: Arc<Client>,
+        client: Arc<HttpClient>,
     ) -> Self {
         Self {
             id,
@@ -47,9 +56,10 @@ impl Book {
         }
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(book.id = self.id, book.title = %self.title)))]
     pub async fn get_volumes(&self) -> Result<Vec<Volume>, reqwest::Error> {
         let resp = LTIForm::follow(
-            BufferedResponse::new(self.client.get(self.base_url()).send().await?).await?,
+            self.client.send(self.client.get(self.base_url())).await?,
             &self.client,
         )
         .await?;
@@ -63,7 +73,7 @@ impl Book {
                     RegexBuilder::new(
                         r#"<a( class="")? href="(.+?)" target="_blank">[\s\S]+?<img src="(.+?)" />[\s\S]+?<div class="tx"><h1>(.+?)</h1></div>"#
                     ).dot_matches_new_line(true)
-                ).captures_iter(&resp.text())
+                ).captures_iter(resp.text())
                 .map(|c| {
                     Volume::new(
                         self.relative_url(c.get(2).unwrap().as_str()), // URL
@@ -76,12 +86,145 @@ impl Book {
                 .collect();
 
             assert!(!volumes.is_empty());
+            trace_event!(DEBUG, volumes = volumes.len(), "listed volumes");
             Ok(volumes)
         }
     }
 
+    /// Downloads all volumes into one PDF, in the order of `get_volumes`.
+    /// Every volume gets a bookmark with its pages nested below it.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(book.id = self.id, book.title = %self.title)))]
+    pub async fn download_to(
+        &self,
+        out: &mut (dyn AsyncWrite + Unpin + Send),
+        profile: &OutputProfile,
+    ) -> Result<Vec<VolumeManifest>, DigiDownloadError> {
+        self.write_volumes(out, profile, None).await
+    }
+
+    /// Like `download_to`, but pages that can't be downloaded or converted
+    /// are retried according to `policy` and then replaced, see `Scraper::write_pages_best_effort`.
+    /// The report lists every replaced page.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(book.id = self.id, book.title = %self.title)))]
+    pub async fn download_to_best_effort(
+        &self,
+        out: &mut (dyn AsyncWrite + Unpin + Send),
+        profile: &OutputProfile,
+        policy: &RetryPolicy,
+    ) -> Result<(Vec<VolumeManifest>, DownloadReport), DigiDownloadError> {
+        let volumes = self.write_volumes(out, profile, Some(policy)).await?;
+        let report = DownloadReport::for_volumes(&volumes);
+        Ok((volumes, report))
+    }
+
+    /// Also saves a `Manifest` next to the file.
+    pub async fn download_to_file(
+        &self,
+        path: &Path,
+        profile: &OutputProfile,
+    ) -> Result<Manifest, DigiDownloadError> {
+        let mut file = BufWriter::new(File::create(path).await?);
+        let volumes = self.download_to(&mut file, profile).await?;
+        drop(file);
+
+        Ok(self.save_manifest(path, volumes).await?)
+    }
+
+    /// Like `download_to_file`, but replaces failing pages like `download_to_best_effort`.
+    pub async fn download_to_file_best_effort(
+        &self,
+        path: &Path,
+        profile: &OutputProfile,
+        policy: &RetryPolicy,
+    ) -> Result<(Manifest, DownloadReport), DigiDownloadError> {
+        let mut file = BufWriter::new(File::create(path).await?);
+        let (volumes, report) = self
+            .download_to_best_effort(&mut file, profile, policy)
+            .await?;
+        drop(file);
+
+        Ok((self.save_manifest(path, volumes).await?, report))
+    }
+
+    /// Downloads every volume into its own file inside `dir`, named after the volume,
+    /// with a `Manifest` next to each file.
+    /// Returns the paths of the written files in the order of `get_volumes`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(book.id = self.id, book.title = %self.title)))]
+    pub async fn download_volumes_to_dir(
+        &self,
+        dir: &Path,
+        profile: &OutputProfile,
+    ) -> Result<Vec<PathBuf>, DigiDownloadError> {
+        tokio::fs::create_dir_all(dir).await?;
+
+        let volumes = self.get_volumes().await?;
+        let mut paths = Vec::with_capacity(volumes.len());
+
+        for (i, volume) in volumes.iter().enumerate() {
+            // volume names aren't guaranteed to be unique, the index keeps the files apart and in order
+            let path = dir.join(format!(
+                "{:02} {}.pdf",
+                i + 1,
+                sanitize_file_name(volume.name())
+            ));
+
+            let mut file = BufWriter::new(File::create(&path).await?);
+            let manifest = volume
+                .get_scraper()
+                .await?
+                .download_book_to(&mut file, profile)
+                .await?;
+            drop(file);
+
+            self.save_manifest(&path, vec![manifest.with_volume(volume)])
+                .await?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+
+    /// Writes every volume, replacing failing pages if a `policy` is given.
+    async fn write_volumes(
+        &self,
+        out: &mut (dyn AsyncWrite + Unpin + Send),
+        profile: &OutputProfile,
+        policy: Option<&RetryPolicy>,
+    ) -> Result<Vec<VolumeManifest>, DigiDownloadError> {
+        let mut writer = PdfWriter::new(out).await.map_err(ScraperError::from)?;
+        let mut manifests = Vec::new();
+
+        for volume in self.get_volumes().await? {
+            let scraper = volume.get_scraper().await?;
+            let title = Some(volume.name().clone());
+            let manifest = match policy {
+                Some(policy) => {
+                    scraper
+                        .write_book_best_effort(&mut writer, profile, title, policy)
+                        .await?
+                }
+                None => scraper.write_book(&mut writer, profile, title).await?,
+            };
+            manifests.push(manifest.with_volume(&volume));
+        }
+
+        writer.finish().await.map_err(ScraperError::from)?;
+        Ok(manifests)
+    }
+
+    /// Saves the manifest of the already written file at `path`.
+    pub(crate) async fn save_manifest(
+        &self,
+        path: &Path,
+        volumes: Vec<VolumeManifest>,
+    ) -> Result<Manifest, std::io::Error> {
+        let manifest = Manifest::for_file(path, volumes).await?.with_book(self);
+        manifest.save(path).await?;
+        Ok(manifest)
+    }
+
     // Needed for `Volume::from_single_volume_book`
-    pub(crate) fn client(&self) -> Arc<Client> {
+    pub(crate) fn client(&self) -> Arc<HttpClient> {
         self.client.clone()
     }
 
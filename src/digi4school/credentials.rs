@@ -0,0 +1,258 @@
+use crate::error::CredentialError;
+use async_trait::async_trait;
+use getset::Getters;
+use serde::Deserialize;
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+
+/// Email and password of a digi4school account.
+#[derive(Clone, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct Credentials {
+    email: String,
+    password: String,
+}
+
+impl Credentials {
+    pub fn new(email: impl Into<String>, password: impl Into<String>) -> Self {
+        Self {
+            email: email.into(),
+            password: password.into(),
+        }
+    }
+}
+
+// keeps the password out of logs
+impl Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("email", &self.email)
+            .field("password", &"<redacted>")
+            .finish()
+    }
+}
+
+/// Supplies the credentials for a `Session`.
+/// Asked for the initial login and again whenever the session expired,
+/// so credentials don't have to be kept in memory by the caller.
+#[async_trait]
+pub trait CredentialProvider: Send + Sync + Debug {
+    async fn credentials(&self) -> Result<Credentials, CredentialError>;
+}
+
+/// Credentials that are already known.
+#[async_trait]
+impl CredentialProvider for Credentials {
+    async fn credentials(&self) -> Result<Credentials, CredentialError> {
+        Ok(self.clone())
+    }
+}
+
+/// Reads the credentials from environment variables,
+/// `DIGI4SCHOOL_EMAIL` and `DIGI4SCHOOL_PASSWORD` by default.
+#[derive(Debug, Clone)]
+pub struct EnvCredentials {
+    email_var: String,
+    password_var: String,
+}
+
+impl EnvCredentials {
+    pub fn new(email_var: impl Into<String>, password_var: impl Into<String>) -> Self {
+        Self {
+            email_var: email_var.into(),
+            password_var: password_var.into(),
+        }
+    }
+
+    fn var(name: &str) -> Result<String, CredentialError> {
+        std::env::var(name).map_err(|_| CredentialError::Missing(name.to_string()))
+    }
+}
+
+impl Default for EnvCredentials {
+    fn default() -> Self {
+        Self::new("DIGI4SCHOOL_EMAIL", "DIGI4SCHOOL_PASSWORD")
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for EnvCredentials {
+    async fn credentials(&self) -> Result<Credentials, CredentialError> {
+        Ok(Credentials::new(
+            Self::var(&self.email_var)?,
+            Self::var(&self.password_var)?,
+        ))
+    }
+}
+
+/// Reads the credentials from a JSON file of the form `{"email": "...", "password": "..."}`.
+/// The file is read again for every login, so it can be changed while the session is in use.
+#[derive(Debug, Clone)]
+pub struct ConfigFileCredentials {
+    path: PathBuf,
+}
+
+impl ConfigFileCredentials {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for ConfigFileCredentials {
+    async fn credentials(&self) -> Result<Credentials, CredentialError> {
+        let buf = tokio::fs::read(&self.path).await?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+}
+
+/// Asks the user, e.g. in a terminal or a login dialog.
+/// The callback runs on the async runtime, so it should return quickly or be cheap to block on.
+pub struct PromptCredentials {
+    prompt: Box<dyn Fn() -> Option<Credentials> + Send + Sync>,
+}
+
+impl PromptCredentials {
+    /// `prompt` returns `None` if the user cancelled the login.
+    pub fn new(prompt: impl Fn() -> Option<Credentials> + Send + Sync + 'static) -> Self {
+        Self {
+            prompt: Box::new(prompt),
+        }
+    }
+}
+
+impl Debug for PromptCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PromptCredentials").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for PromptCredentials {
+    async fn credentials(&self) -> Result<Credentials, CredentialError> {
+        (self.prompt)().ok_or(CredentialError::Cancelled)
+    }
+}
+
+/// Reads the credentials from a file containing the email on the first and the password on the second line,
+/// like secrets mounted into containers.
+///
+/// On Unix the file must not be accessible by the group or others.
+#[derive(Debug, Clone)]
+pub struct SecretFileCredentials {
+    path: PathBuf,
+}
+
+impl SecretFileCredentials {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    #[cfg(unix)]
+    async fn check_permissions(path: &Path) -> Result<(), CredentialError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = tokio::fs::metadata(path).await?.permissions().mode();
+        match mode & 0o077 {
+            0 => Ok(()),
+            _ => Err(CredentialError::InsecurePermissions(path.to_path_buf())),
+        }
+    }
+
+    #[cfg(not(unix))]
+    async fn check_permissions(_path: &Path) -> Result<(), CredentialError> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CredentialProvider for SecretFileCredentials {
+    async fn credentials(&self) -> Result<Credentials, CredentialError> {
+        Self::check_permissions(&self.path).await?;
+
+        let content = tokio::fs::read_to_string(&self.path).await?;
+        let mut lines = content.lines();
+
+        match (lines.next(), lines.next()) {
+            (Some(email), Some(password)) if !email.trim().is_empty() => {
+                // passwords may start or end with spaces, only the line break is removed
+                Ok(Credentials::new(email.trim(), password))
+            }
+            _ => Err(CredentialError::Malformed(self.path.clone())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("digi_download_{name}_{}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn reads_environment_variables() {
+        // unique names, tests run in parallel
+        let provider =
+            EnvCredentials::new("DIGI_DOWNLOAD_TEST_EMAIL", "DIGI_DOWNLOAD_TEST_PASSWORD");
+        let missing = provider.credentials().await;
+        assert!(
+            matches!(&missing, Err(CredentialError::Missing(var)) if var == "DIGI_DOWNLOAD_TEST_EMAIL"),
+            "{missing:?}"
+        );
+
+        std::env::set_var("DIGI_DOWNLOAD_TEST_EMAIL", "a@b.at");
+        std::env::set_var("DIGI_DOWNLOAD_TEST_PASSWORD", "secret");
+        let credentials = provider.credentials().await.unwrap();
+        assert_eq!(credentials.email(), "a@b.at");
+        assert_eq!(credentials.password(), "secret");
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_config_file() {
+        let path = temp_file("config.json");
+        tokio::fs::write(&path, r#"{"email": "a@b.at"}"#)
+            .await
+            .unwrap();
+        let result = ConfigFileCredentials::new(&path).credentials().await;
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert!(
+            matches!(result, Err(CredentialError::Json(_))),
+            "{result:?}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn rejects_secret_file_readable_by_others() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_file("secret");
+        tokio::fs::write(&path, "a@b.at\n secret \n").await.unwrap();
+        let provider = SecretFileCredentials::new(&path);
+
+        let mut results = Vec::new();
+        for mode in [0o640, 0o604, 0o600] {
+            let permissions = std::fs::Permissions::from_mode(mode);
+            tokio::fs::set_permissions(&path, permissions)
+                .await
+                .unwrap();
+            results.push(provider.credentials().await);
+        }
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        assert!(matches!(
+            results[0],
+            Err(CredentialError::InsecurePermissions(_))
+        ));
+        assert!(matches!(
+            results[1],
+            Err(CredentialError::InsecurePermissions(_))
+        ));
+        let credentials = results[2].as_ref().unwrap();
+        assert_eq!(credentials.email(), "a@b.at");
+        assert_eq!(credentials.password(), " secret ");
+    }
+}
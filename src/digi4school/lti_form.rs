@@ -1,5 +1,6 @@
 use crate::buffered_response::BufferedResponse;
-use reqwest::{Client, Method, Url};
+use crate::http::HttpClient;
+use reqwest::{Method, Url};
 use scraper::{ElementRef, Html, Selector};
 use std::collections::HashMap;
 use std::str::FromStr;
@@ -17,7 +18,7 @@ pub(crate) struct LTIForm {
 impl LTIForm {
     pub async fn follow(
         resp: BufferedResponse,
-        client: &Client,
+        client: &HttpClient,
     ) -> Result<BufferedResponse, reqwest::Error> {
         Ok(match LTIForm::new(&resp) {
             Some(form) => form.follow_recursively(client).await?,
@@ -26,7 +27,7 @@ impl LTIForm {
     }
 
     pub fn new(raw_form: &BufferedResponse) -> Option<Self> {
-        let doc = Html::parse_document(&raw_form.text());
+        let doc = Html::parse_document(raw_form.text());
         let selector = Selector::parse("form#lti").unwrap();
 
         let mut iter = doc.root_element().select(&selector);
@@ -79,9 +80,10 @@ impl LTIForm {
         })
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(url = %self.url)))]
     pub async fn follow_recursively(
         self,
-        client: &Client,
+        client: &HttpClient,
     ) -> Result<BufferedResponse, reqwest::Error> {
         let mut form = self;
 
@@ -98,16 +100,15 @@ impl LTIForm {
         }
     }
 
-    async fn send(self, client: &Client) -> Result<BufferedResponse, reqwest::Error> {
-        BufferedResponse::new(
-            client
-                .request(self.method, self.url)
-                .header("Content-Type", "application/x-www-form-urlencoded")
-                .body(serde_urlencoded::to_string(self.form_data).unwrap())
-                .send()
-                .await?,
-        )
-        .await
+    async fn send(self, client: &HttpClient) -> Result<BufferedResponse, reqwest::Error> {
+        client
+            .send(
+                client
+                    .request(self.method, self.url)
+                    .header("Content-Type", "application/x-www-form-urlencoded")
+                    .body(serde_urlencoded::to_string(self.form_data).unwrap()),
+            )
+            .await
     }
 
     fn expect_form_attr(form_html: ElementRef, attribute: &str, alias: &str) -> String {
@@ -1,4 +1,7 @@
+pub mod attachment;
 pub mod book;
+pub mod credentials;
 mod lti_form;
 pub mod session;
+pub mod session_pool;
 pub mod volume;
@@ -1,13 +1,143 @@
+use crate::buffered_response::BufferedResponse;
 use crate::digi4school::book::Book;
+use crate::digi4school::credentials::{CredentialProvider, Credentials};
 use crate::error::LoginError;
-use crate::regex;
-use reqwest::{Client, Url};
+use crate::http::har::HarRecorder;
+use crate::http::replay::Replay;
+use crate::http::{HttpClient, Middleware, Next};
+use crate::{regex, trace_event};
+use async_trait::async_trait;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::header::{HeaderValue, CONTENT_TYPE, COOKIE, SET_COOKIE};
+use reqwest::{Client, Request, RequestBuilder, Response, Url};
 use serde::Serialize;
 use std::str::FromStr;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
 
 pub struct Session {
-    client: Arc<Client>,
+    client: Arc<HttpClient>,
+    /// Shared with `client`, so books and volumes lose access once it is cleared.
+    cookies: Arc<SessionCookies>,
+    /// Shared with the `Relogin` middleware of `client`.
+    login: Arc<LoginState>,
+}
+
+#[derive(Debug)]
+struct LoginState {
+    /// Asked again if the session expired.
+    credentials: Box<dyn CredentialProvider>,
+    /// Prevents logging in again after `logout`.
+    logged_out: AtomicBool,
+    /// Number of logins, so requests noticing the same expiry log in only once.
+    logins: AtomicU64,
+    relogin: tokio::sync::Mutex<()>,
+}
+
+/// Logs in again if a request ends up on the login page because the session expired,
+/// and repeats the request, so books and volumes keep working.
+#[derive(Debug)]
+struct Relogin {
+    login: Arc<LoginState>,
+    /// Only builds the login request, it is sent through the rest of the middleware.
+    client: Client,
+}
+
+#[async_trait]
+impl Middleware for Relogin {
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, reqwest::Error> {
+        let logins = self.login.logins.load(Ordering::Acquire);
+        let retry = request.try_clone();
+        let resp = next.run(request).await?;
+
+        if !Session::is_login_page(resp.url()) || self.login.logged_out.load(Ordering::Acquire) {
+            return Ok(resp);
+        }
+        let Some(retry) = retry else {
+            return Ok(resp);
+        };
+
+        match self.relogin(logins, next).await {
+            Ok(()) => next.run(retry).await,
+            Err(_error) => {
+                trace_event!(WARN, error = %_error, "logging in again failed");
+                Ok(resp)
+            }
+        }
+    }
+}
+
+impl Relogin {
+    /// Logs in, unless another request already did since `logins` was read.
+    async fn relogin(&self, logins: u64, next: Next<'_>) -> Result<(), LoginError> {
+        let _guard = self.login.relogin.lock().await;
+        if self.login.logins.load(Ordering::Acquire) != logins {
+            return Ok(());
+        }
+
+        trace_event!(INFO, "session expired, logging in again");
+        let credentials = self.login.credentials.credentials().await?;
+        let request =
+            Session::login_request(self.client.post(Session::LOGIN_URL), &credentials, false)
+                .build()?;
+        let resp = BufferedResponse::new(next.run(request).await?).await?;
+        Session::login_result(&resp)?;
+
+        self.login.logins.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+}
+
+/// Cookie store that, unlike reqwest's `Jar`, can be cleared.
+#[derive(Debug, Default)]
+struct SessionCookies {
+    jar: RwLock<Jar>,
+    is_empty: AtomicBool,
+}
+
+impl SessionCookies {
+    fn clear(&self) {
+        *self.jar.write().unwrap() = Jar::default();
+        self.is_empty.store(true, Ordering::Release);
+    }
+}
+
+impl CookieStore for SessionCookies {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        self.jar.read().unwrap().set_cookies(cookie_headers, url);
+        self.is_empty.store(false, Ordering::Release);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        self.jar.read().unwrap().cookies(url)
+    }
+}
+
+/// Sends and stores the session cookies, so they also work with transports other than the reqwest client.
+/// The reqwest client uses the same store for the redirects it follows, which don't pass through middleware.
+#[derive(Debug)]
+struct Cookies(Arc<SessionCookies>);
+
+#[async_trait]
+impl Middleware for Cookies {
+    async fn handle(
+        &self,
+        mut request: Request,
+        next: Next<'_>,
+    ) -> Result<Response, reqwest::Error> {
+        if !request.headers().contains_key(COOKIE) {
+            if let Some(cookies) = self.0.cookies(request.url()) {
+                request.headers_mut().insert(COOKIE, cookies);
+            }
+        }
+
+        let resp = next.run(request).await?;
+        if resp.headers().contains_key(SET_COOKIE) {
+            self.0
+                .set_cookies(&mut resp.headers().get_all(SET_COOKIE).iter(), resp.url());
+        }
+        Ok(resp)
+    }
 }
 
 #[derive(Serialize)]
@@ -19,9 +149,51 @@ struct LoginData {
 
 impl Session {
     pub(crate) const BASE_URL: &'static str = "https://digi4school.at";
+    const LOGIN_URL: &'static str = "https://digi4school.at/br/xhr/login";
+    const LOGIN_PAGE_PATH: &'static str = "/login";
+    const LOGOUT_PATH: &'static str = "/br/logout";
 
     pub async fn new(email: String, password: String) -> Result<Self, LoginError> {
-        let builder = Client::builder().cookie_store(true);
+        Self::with_credentials(Credentials::new(email, password)).await
+    }
+
+    /// Logs in with the credentials of `provider`, which is asked again whenever the session expires.
+    pub async fn with_credentials(
+        provider: impl CredentialProvider + 'static,
+    ) -> Result<Self, LoginError> {
+        Self::with_http_client(provider, |client| client).await
+    }
+
+    /// Like `with_credentials`, but records all traffic of the session (including the login) into `recorder`.
+    /// See `HarRecorder` for what is redacted.
+    pub async fn with_recorder(
+        provider: impl CredentialProvider + 'static,
+        recorder: Arc<HarRecorder>,
+    ) -> Result<Self, LoginError> {
+        Self::with_http_client(provider, |client| client.with_recorder(recorder)).await
+    }
+
+    /// A session whose requests are answered by `replay` instead of the portal,
+    /// e.g. to reproduce a bug from a recorded session without its credentials.
+    /// The recorded login is replayed no matter which credentials it was recorded with.
+    pub async fn replay(replay: Arc<Replay>) -> Result<Self, LoginError> {
+        Self::with_http_client(Credentials::new("", ""), |client| {
+            client.with_transport(replay)
+        })
+        .await
+    }
+
+    /// Like `with_credentials`, but `configure` can add middleware or replace the transport
+    /// of the client all requests of the session go through.
+    /// The session cookies are added to requests before they reach the added middleware,
+    /// and taken from its responses, so they work with any transport.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn with_http_client(
+        provider: impl CredentialProvider + 'static,
+        configure: impl FnOnce(HttpClient) -> HttpClient,
+    ) -> Result<Self, LoginError> {
+        let cookies = Arc::new(SessionCookies::default());
+        let builder = Client::builder().cookie_provider(cookies.clone());
 
         #[cfg(feature = "route_burp")]
         let builder = builder
@@ -30,26 +202,103 @@ impl Session {
             )
             .proxy(reqwest::Proxy::https("127.0.0.1:8080").unwrap());
 
+        let client = builder.build().unwrap();
+        let login = Arc::new(LoginState {
+            credentials: Box::new(provider),
+            logged_out: AtomicBool::new(false),
+            logins: AtomicU64::new(0),
+            relogin: tokio::sync::Mutex::new(()),
+        });
+        let relogin = Relogin {
+            login: login.clone(),
+            client: client.clone(),
+        };
+
         let session = Self {
-            client: Arc::new(builder.build().unwrap()),
+            client: Arc::new(configure(
+                HttpClient::new(client)
+                    .with_middleware(Arc::new(relogin))
+                    .with_middleware(Arc::new(Cookies(cookies.clone()))),
+            )),
+            cookies,
+            login,
         };
-        session.login(email, password, false).await?;
+        session.relogin().await?;
 
         Ok(session)
     }
 
-    pub async fn get_books(&self) -> Result<Vec<Book>, reqwest::Error> {
-        let resp = self
+    /// Logs in again with freshly requested credentials.
+    /// All requests of the session do this automatically if the session expired, but not after `logout`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn relogin(&self) -> Result<(), LoginError> {
+        let credentials = self.login.credentials.credentials().await?;
+        self.login(&credentials, false).await?;
+
+        self.login.logged_out.store(false, Ordering::Release);
+        self.login.logins.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Ends the session on the portal and forgets all cookies,
+    /// which also ends the access of all books and volumes obtained from this session.
+    /// Sessions aren't persisted to disk, so nothing else is left behind.
+    ///
+    /// The cookies are cleared even if the portal couldn't be reached.
+    /// Use `is_logged_in` to verify that the portal no longer accepts the session.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn logout(&self) -> Result<(), reqwest::Error> {
+        self.login.logged_out.store(true, Ordering::Release);
+
+        trace_event!(INFO, "logging out");
+        let result = self
             .client
-            .get(format!("{}/ebooks", Self::BASE_URL))
-            .send()
-            .await?;
+            .send(
+                self.client
+                    .get(format!("{}{}", Self::BASE_URL, Self::LOGOUT_PATH)),
+            )
+            .await;
+        self.cookies.clear();
+
+        result?.error_for_status_ref().map(|_| ())
+    }
+
+    /// The recorder passed to `with_recorder`.
+    pub fn recorder(&self) -> Option<&Arc<HarRecorder>> {
+        self.client.recorder()
+    }
+
+    /// Whether any cookies are stored, a session can't be valid without them.
+    /// This holds for any transport, the session stores the cookies itself.
+    /// Checks only the local state, see `is_logged_in` for asking the portal.
+    pub fn has_cookies(&self) -> bool {
+        !self.cookies.is_empty.load(Ordering::Acquire)
+    }
+
+    /// Whether the portal still accepts the session.
+    pub async fn is_logged_in(&self) -> Result<bool, reqwest::Error> {
+        let resp = self.get_ebooks_page().await?;
+        Ok(!Self::is_logged_out(&resp))
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all))]
+    pub async fn get_books(&self) -> Result<Vec<Book>, LoginError> {
+        let mut resp = self.get_ebooks_page().await?;
+        if Self::is_logged_out(&resp) {
+            if self.login.logged_out.load(Ordering::Acquire) {
+                return Err(LoginError::LoggedOut);
+            }
+
+            trace_event!(INFO, "session expired, logging in again");
+            self.relogin().await?;
+            resp = self.get_ebooks_page().await?;
+        }
 
-        Ok(
+        let books: Vec<Book> =
             regex!(
                 r"data-code='(.+?)' data-id='(\d+?)'.+?<img src='(.+?)'>.+?<h1>(.+?)</h1>.+?bis (\d{1,2}\.\d{1,2})\.(\d+)"
             )
-            .captures_iter(&resp.text().await?)
+            .captures_iter(resp.text())
             .inspect(|m| assert_eq!(m.get(5).unwrap().as_str(), "31.10"))
             .map(|m| {
                 Book::new(
@@ -61,40 +310,195 @@ impl Session {
                     self.client.clone(),
                 )
             })
-            .collect())
+            .collect();
+
+        trace_event!(DEBUG, books = books.len(), "listed books");
+        Ok(books)
+    }
+
+    async fn get_ebooks_page(&self) -> Result<BufferedResponse, reqwest::Error> {
+        self.client
+            .send(self.client.get(format!("{}/ebooks", Self::BASE_URL)))
+            .await
+    }
+
+    /// Without a valid session the portal redirects from the book list to the login page.
+    fn is_logged_out(resp: &reqwest::Response) -> bool {
+        resp.url().path().trim_end_matches('/') != "/ebooks"
+    }
+
+    /// Where the portal redirects requests of an expired session.
+    fn is_login_page(url: &Url) -> bool {
+        url.host_str() == Some("digi4school.at")
+            && url.path().trim_end_matches('/') == Self::LOGIN_PAGE_PATH
     }
 
     pub fn redeem_code(&self) -> bool {
         todo!("Program once I can test redeeming a code")
     }
 
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(skip_all, fields(remember_login = remember_login))
+    )]
     async fn login(
         &self,
-        email: String,
-        password: String,
+        credentials: &Credentials,
         remember_login: bool,
     ) -> Result<(), LoginError> {
-        let resp_content = self
+        let resp = self
             .client
-            .post(format!("{}/br/xhr/login", Self::BASE_URL))
-            .header("Content-Type", "application/x-www-form-urlencoded")
+            .send(Self::login_request(
+                self.client.post(Self::LOGIN_URL),
+                credentials,
+                remember_login,
+            ))
+            .await?;
+
+        Self::login_result(&resp)
+    }
+
+    fn login_request(
+        request: RequestBuilder,
+        credentials: &Credentials,
+        remember_login: bool,
+    ) -> RequestBuilder {
+        request
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
             .body(
                 serde_urlencoded::to_string(LoginData {
-                    email,
-                    password,
+                    email: credentials.email().clone(),
+                    password: credentials.password().clone(),
                     indefinite: u8::from(remember_login),
                 })
                 .unwrap(),
             )
-            .send()
-            .await?
-            .text()
-            .await?;
+    }
 
-        match resp_content.as_str() {
-            "OK" => Ok(()),
-            "KO" => Err(LoginError::BadLogin),
-            _ => panic!("Bad login-form response: {}", resp_content),
+    fn login_result(resp: &BufferedResponse) -> Result<(), LoginError> {
+        match resp.text() {
+            "OK" => {
+                trace_event!(INFO, "logged in");
+                Ok(())
+            }
+            "KO" => {
+                trace_event!(WARN, "login rejected");
+                Err(LoginError::BadLogin)
+            }
+            _ => panic!("Bad login-form response: {}", resp.text()),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digi4school::credentials::PromptCredentials;
+    use crate::http::har::Har;
+    use crate::http::Transport;
+    use std::sync::Mutex;
+
+    /// Replays responses and remembers the cookies sent to `/ebooks`.
+    #[derive(Debug)]
+    struct CookieTransport {
+        replay: Replay,
+        sent: Mutex<Vec<Option<HeaderValue>>>,
+    }
+
+    #[async_trait]
+    impl Transport for CookieTransport {
+        async fn execute(&self, request: Request) -> Result<Response, reqwest::Error> {
+            if request.url().path() == "/ebooks" {
+                let cookie = request.headers().get(COOKIE).cloned();
+                self.sent.lock().unwrap().push(cookie);
+            }
+            self.replay.execute(request).await
+        }
+    }
+
+    fn replay() -> Replay {
+        let har: Har = serde_json::from_str(
+            r#"{"log":{"version":"1.2","creator":{"name":"test","version":"1"},"entries":[
+                {"request":{"method":"POST","url":"https://digi4school.at/br/xhr/login"},
+                 "response":{"status":200,"headers":[{"name":"set-cookie","value":"sid=1; Path=/"}],"content":{"text":"OK"}}},
+                {"request":{"method":"GET","url":"https://digi4school.at/ebooks"},
+                 "response":{"status":200,"content":{"text":"<a data-code='x' data-id='123' ><img src='https://a.digi4school.at/123.png'> <h1>Mathematik</h1> bis 31.10.2030</a>"}}},
+                {"request":{"method":"GET","url":"https://a.digi4school.at/ebook/123"},
+                 "response":{"status":302,"redirectURL":"https://digi4school.at/login","content":{}}},
+                {"request":{"method":"GET","url":"https://a.digi4school.at/ebook/123"},
+                 "response":{"status":200,"content":{"text":"<a href=\"1/index.html\" target=\"_blank\"> <img src=\"1/thumb.jpg\" /> <div class=\"tx\"><h1>Schulbuch</h1></div></a>"}}},
+                {"request":{"method":"GET","url":"https://digi4school.at/login"},
+                 "response":{"status":200,"content":{"text":"<html></html>"}}}
+            ]}}"#,
+        )
+        .unwrap();
+        Replay::from_har(har)
+    }
+
+    #[tokio::test]
+    async fn logs_in_again_when_redirected_to_the_login_page() {
+        let session = Session::replay(Arc::new(replay())).await.unwrap();
+        let book = session.get_books().await.unwrap().remove(0);
+
+        let volumes = book.get_volumes().await.unwrap();
+        assert_eq!(volumes.len(), 1);
+        assert_eq!(volumes[0].name(), "Schulbuch");
+    }
+
+    #[test]
+    fn detects_login_page() {
+        assert!(Session::is_login_page(
+            &Url::parse("https://digi4school.at/login").unwrap()
+        ));
+        assert!(!Session::is_login_page(
+            &Url::parse("https://digi4school.at/ebooks").unwrap()
+        ));
+        assert!(!Session::is_login_page(
+            &Url::parse("https://a.digi4school.at/login").unwrap()
+        ));
+    }
+
+    #[tokio::test]
+    async fn sends_cookies_through_custom_transports() {
+        let transport = Arc::new(CookieTransport {
+            replay: replay(),
+            sent: Mutex::new(Vec::new()),
+        });
+        let session = Session::with_http_client(Credentials::new("", ""), |client| {
+            client.with_transport(transport.clone())
+        })
+        .await
+        .unwrap();
+        assert!(session.has_cookies());
+
+        session.get_books().await.unwrap();
+        assert_eq!(
+            transport.sent.lock().unwrap().as_slice(),
+            [Some(HeaderValue::from_static("sid=1"))]
+        );
+
+        // the logout isn't recorded, but the cookies are cleared anyway
+        let _ = session.logout().await;
+        assert!(!session.has_cookies());
+    }
+
+    #[tokio::test]
+    async fn prompts_again_for_relogin() {
+        let prompts = Arc::new(AtomicU64::new(0));
+        let counter = prompts.clone();
+        let provider = PromptCredentials::new(move || {
+            counter.fetch_add(1, Ordering::AcqRel);
+            Some(Credentials::new("", ""))
+        });
+
+        let session =
+            Session::with_http_client(provider, |client| client.with_transport(Arc::new(replay())))
+                .await
+                .unwrap();
+        assert_eq!(prompts.load(Ordering::Acquire), 1);
+
+        let book = session.get_books().await.unwrap().remove(0);
+        book.get_volumes().await.unwrap();
+        assert_eq!(prompts.load(Ordering::Acquire), 2);
+    }
+}
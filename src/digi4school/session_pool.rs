@@ -0,0 +1,343 @@
+use crate::digi4school::book::Book;
+use crate::digi4school::credentials::{CredentialProvider, Credentials};
+use crate::digi4school::session::Session;
+use crate::error::{CredentialError, DigiDownloadError, LoginError};
+use crate::export::{export_book, ExportFailure, ExportOptions, ExportReport};
+use futures_util::{stream, StreamExt};
+use getset::{CopyGetters, Getters};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+/// Limits shared by all accounts of a `SessionPool`, so many accounts don't overload the portal.
+#[derive(Debug, Clone, Copy, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct RateLimit {
+    /// Number of accounts listing books or books being downloaded at once.
+    max_concurrent: usize,
+    /// Minimum time between the start of two operations.
+    min_interval: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(max_concurrent: usize, min_interval: Duration) -> Self {
+        Self {
+            max_concurrent,
+            min_interval,
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new(4, Duration::ZERO)
+    }
+}
+
+#[derive(Debug)]
+struct RateLimiter {
+    limit: RateLimit,
+    permits: Semaphore,
+    next_start: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            permits: Semaphore::new(limit.max_concurrent.max(1)),
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until another operation may start, it ends once the permit is dropped.
+    async fn acquire(&self) -> SemaphorePermit<'_> {
+        let permit = self.permits.acquire().await.unwrap();
+
+        let mut next_start = self.next_start.lock().await;
+        tokio::time::sleep_until(*next_start).await;
+        *next_start = Instant::now() + self.limit.min_interval;
+
+        permit
+    }
+}
+
+/// A book owned by at least one account of a `SessionPool`.
+#[derive(Debug, Getters)]
+#[getset(get = "pub")]
+pub struct PooledBook {
+    /// The book as listed by the first owner, it is downloaded with that account.
+    book: Book,
+    /// Accounts owning the book, in the order of the pool.
+    owners: Vec<String>,
+}
+
+/// Manages the sessions of many accounts, e.g. all students of a school.
+/// Accounts are keyed by an arbitrary name, usually their email.
+pub struct SessionPool {
+    sessions: BTreeMap<String, Session>,
+    limiter: RateLimiter,
+}
+
+impl SessionPool {
+    pub fn new(rate_limit: RateLimit) -> Self {
+        Self {
+            sessions: BTreeMap::new(),
+            limiter: RateLimiter::new(rate_limit),
+        }
+    }
+
+    /// Replaces an existing session of the same account.
+    pub fn add(&mut self, account: impl Into<String>, session: Session) {
+        self.sessions.insert(account.into(), session);
+    }
+
+    pub fn remove(&mut self, account: &str) -> Option<Session> {
+        self.sessions.remove(account)
+    }
+
+    pub fn get(&self, account: &str) -> Option<&Session> {
+        self.sessions.get(account)
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &str> {
+        self.sessions.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Logs in every account (keyed by its email) within the rate limit.
+    /// A failing login doesn't stop the others, the failed accounts are returned instead.
+    pub async fn login_all(&mut self, accounts: Vec<Credentials>) -> Vec<(String, LoginError)> {
+        let limiter = &self.limiter;
+        let results: Vec<(String, Result<Session, LoginError>)> = stream::iter(accounts)
+            .map(|credentials| async move {
+                let _permit = limiter.acquire().await;
+                let account = credentials.email().clone();
+                (account, Session::with_credentials(credentials).await)
+            })
+            .buffer_unordered(limiter.limit.max_concurrent.max(1))
+            .collect()
+            .await;
+
+        let mut failed = Vec::new();
+        for (account, result) in results {
+            match result {
+                Ok(session) => self.add(account, session),
+                Err(error) => failed.push((account, error)),
+            }
+        }
+
+        failed
+    }
+
+    /// Logs in a single account whose credentials come from `provider`.
+    pub async fn login(
+        &mut self,
+        account: impl Into<String>,
+        provider: impl CredentialProvider + 'static,
+    ) -> Result<(), LoginError> {
+        let session = {
+            let _permit = self.limiter.acquire().await;
+            Session::with_credentials(provider).await?
+        };
+
+        self.add(account, session);
+        Ok(())
+    }
+
+    /// Logs out every account, see `Session::logout`.
+    /// Returns the accounts whose logout request failed, their cookies are cleared anyway.
+    pub async fn logout_all(&self) -> Vec<(String, reqwest::Error)> {
+        let mut failed = Vec::new();
+        for (account, session) in &self.sessions {
+            if let Err(error) = session.logout().await {
+                failed.push((account.clone(), error));
+            }
+        }
+
+        failed
+    }
+
+    /// Reads accounts from a CSV file with the email in the first and the password in the second column.
+    /// A header row starting with `email`, empty lines and lines starting with `#` are skipped.
+    pub async fn read_csv(path: &Path) -> Result<Vec<Credentials>, CredentialError> {
+        let content = tokio::fs::read_to_string(path).await?;
+
+        let mut accounts = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields = split_csv_line(line);
+            match &fields[..] {
+                [email, ..] if i == 0 && email.trim().eq_ignore_ascii_case("email") => {}
+                [email, password, ..] if !email.trim().is_empty() => {
+                    accounts.push(Credentials::new(email.trim(), password.as_str()))
+                }
+                _ => return Err(CredentialError::Malformed(path.to_path_buf())),
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    /// Reads accounts from a JSON file containing a list of `{"email": "...", "password": "..."}` objects.
+    pub async fn read_config(path: &Path) -> Result<Vec<Credentials>, CredentialError> {
+        let buf = tokio::fs::read(path).await?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    /// Lists the books of every account.
+    pub async fn get_books(&self) -> BTreeMap<String, Result<Vec<Book>, LoginError>> {
+        stream::iter(&self.sessions)
+            .map(|(account, session)| async move {
+                let _permit = self.limiter.acquire().await;
+                (account.clone(), session.get_books().await)
+            })
+            .buffer_unordered(self.limiter.limit.max_concurrent.max(1))
+            .collect()
+            .await
+    }
+
+    /// Lists every book owned by any account only once.
+    /// Also returns the accounts whose books couldn't be listed.
+    pub async fn get_unique_books(&self) -> (Vec<PooledBook>, Vec<(String, LoginError)>) {
+        let mut books: BTreeMap<u16, PooledBook> = BTreeMap::new();
+        let mut failed = Vec::new();
+
+        // `get_books` is ordered by account, so the first owner is deterministic
+        for (account, result) in self.get_books().await {
+            match result {
+                Ok(account_books) => {
+                    for book in account_books {
+                        books
+                            .entry(book.id())
+                            .or_insert_with(|| PooledBook {
+                                book,
+                                owners: Vec::new(),
+                            })
+                            .owners
+                            .push(account.clone());
+                    }
+                }
+                Err(error) => failed.push((account, error)),
+            }
+        }
+
+        (books.into_values().collect(), failed)
+    }
+
+    /// Like `export::export_library`, but for the books of all accounts.
+    /// Books owned by multiple accounts are only downloaded once.
+    pub async fn export_library(
+        &self,
+        dir: &Path,
+        options: &ExportOptions,
+    ) -> Result<ExportReport, DigiDownloadError> {
+        let (books, failed_accounts) = self.get_unique_books().await;
+
+        let mut report = ExportReport::default();
+        for (account, error) in failed_accounts {
+            report.add_failure(ExportFailure {
+                book: format!("books of {account}"),
+                volume: None,
+                error: error.into(),
+            });
+        }
+
+        let reports: Vec<Result<ExportReport, DigiDownloadError>> = stream::iter(&books)
+            .map(|pooled| async move {
+                let _permit = self.limiter.acquire().await;
+
+                let mut report = ExportReport::default();
+                export_book(&pooled.book, dir, options, &mut report).await?;
+                Ok(report)
+            })
+            .buffer_unordered(self.limiter.limit.max_concurrent.max(1))
+            .collect()
+            .await;
+
+        for book_report in reports {
+            report.merge(book_report?);
+        }
+
+        Ok(report)
+    }
+}
+
+impl Default for SessionPool {
+    fn default() -> Self {
+        Self::new(RateLimit::default())
+    }
+}
+
+/// Splits a CSV line into its fields, fields in double quotes may contain commas and escaped (`""`) quotes.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_quoted_fields() {
+        assert_eq!(split_csv_line("a@b.at,secret"), ["a@b.at", "secret"]);
+        assert_eq!(split_csv_line(r#"a@b.at,"se,cret""#), ["a@b.at", "se,cret"]);
+        assert_eq!(
+            split_csv_line(r#""a@b.at","say ""hi""",x"#),
+            ["a@b.at", r#"say "hi""#, "x"]
+        );
+        assert_eq!(split_csv_line(",,"), ["", "", ""]);
+    }
+
+    #[tokio::test]
+    async fn reads_accounts_from_csv() {
+        let path =
+            std::env::temp_dir().join(format!("digi_download_accounts_{}.csv", std::process::id()));
+        tokio::fs::write(
+            &path,
+            "email,password\n# teachers\na@b.at,secret\n\n c@d.at ,\"p,w\"\n",
+        )
+        .await
+        .unwrap();
+        let accounts = SessionPool::read_csv(&path).await.unwrap();
+
+        tokio::fs::write(&path, "a@b.at\n").await.unwrap();
+        let malformed = SessionPool::read_csv(&path).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        let accounts: Vec<(&str, &str)> = accounts
+            .iter()
+            .map(|account| (account.email().as_str(), account.password().as_str()))
+            .collect();
+        assert_eq!(accounts, [("a@b.at", "secret"), ("c@d.at", "p,w")]);
+        assert!(matches!(malformed, Err(CredentialError::Malformed(_))));
+    }
+}
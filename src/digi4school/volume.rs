@@ -1,15 +1,22 @@
 use crate::buffered_response::BufferedResponse;
+use crate::digi4school::attachment::Attachment;
 use crate::digi4school::book::Book;
 use crate::digi4school::lti_form::LTIForm;
-use crate::scraper::get_scraper_constructor;
+use crate::error::{DigiDownloadError, ScraperError};
+use crate::http::HttpClient;
+use crate::output_profile::OutputProfile;
 use crate::scraper::scraper_trait::Scraper;
+use crate::scraper::{PdfWriter, ScraperRegistry};
 use getset::Getters;
-use reqwest::{Client, Url};
+use reqwest::Url;
 use std::fmt::Display;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, OnceLock};
+use tokio::io::AsyncWrite;
 
 #[derive(Getters)]
 pub struct Volume {
+    #[getset(get = "pub")]
     url: Url,
     resp: OnceLock<Arc<BufferedResponse>>,
 
@@ -18,11 +25,11 @@ pub struct Volume {
     #[getset(get = "pub")]
     thumbnail: Url,
 
-    client: Arc<Client>,
+    client: Arc<HttpClient>,
 }
 
 impl Volume {
-    pub(crate) fn new(url: Url, name: &str, thumbnail: Url, client: Arc<Client>) -> Self {
+    pub(crate) fn new(url: Url, name: &str, thumbnail: Url, client: Arc<HttpClient>) -> Self {
         Self {
             url,
             resp: OnceLock::default(),
@@ -46,13 +53,105 @@ impl Volume {
         }
     }
 
-    pub async fn get_scraper(&self) -> Result<Box<dyn Scraper>, reqwest::Error> {
+    /// Picks the scraper from `ScraperRegistry::global`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %self.name, url = %self.url)))]
+    pub async fn get_scraper(&self) -> Result<Box<dyn Scraper>, ScraperError> {
         let resp = self.get_response().await?;
+        let constructor = ScraperRegistry::global().get_constructor(&resp)?;
+
+        constructor(resp, self.client.clone())
+    }
+
+    /// Extra material linked on the landing page of the volume.
+    pub async fn get_attachments(&self) -> Result<Vec<Attachment>, reqwest::Error> {
+        Ok(Attachment::from_response(&*self.get_response().await?))
+    }
+
+    pub async fn fetch_attachment(
+        &self,
+        attachment: &Attachment,
+    ) -> Result<BufferedResponse, reqwest::Error> {
+        let resp = self
+            .client
+            .send(self.client.get(attachment.url().clone()))
+            .await?;
+        resp.error_for_status_ref()?;
+
+        Ok(resp)
+    }
+
+    /// Saves every attachment into `dir`.
+    /// Returns the paths of the written files in the order of `get_attachments`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %self.name)))]
+    pub async fn download_attachments(
+        &self,
+        dir: &Path,
+    ) -> Result<Vec<PathBuf>, DigiDownloadError> {
+        tokio::fs::create_dir_all(dir).await?;
+
+        let attachments = self.get_attachments().await?;
+        let mut paths = Vec::with_capacity(attachments.len());
+
+        for (i, attachment) in attachments.iter().enumerate() {
+            let path = dir.join(attachment.numbered_file_name(i));
+
+            tokio::fs::write(&path, self.fetch_attachment(attachment).await?.bytes()).await?;
+            paths.push(path);
+        }
+
+        Ok(paths)
+    }
+
+    /// Downloads the volume and embeds every attachment of at most `max_embed_size` bytes as a file attachment.
+    /// Returns the attachments that were too large to be embedded.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %self.name)))]
+    pub async fn download_with_attachments(
+        &self,
+        out: &mut (dyn AsyncWrite + Unpin + Send),
+        profile: &OutputProfile,
+        max_embed_size: u64,
+    ) -> Result<Vec<Attachment>, DigiDownloadError> {
+        let mut writer = PdfWriter::new(out).await.map_err(ScraperError::from)?;
+        self.get_scraper()
+            .await?
+            .write_book(&mut writer, profile, None)
+            .await?;
+
+        let mut too_large = Vec::new();
+        for (i, attachment) in self.get_attachments().await?.into_iter().enumerate() {
+            // stops downloading the body once it is too large
+            let Some(resp) = self
+                .client
+                .send_limited(self.client.get(attachment.url().clone()), max_embed_size)
+                .await?
+            else {
+                too_large.push(attachment);
+                continue;
+            };
+
+            resp.error_for_status_ref()?;
+
+            let mime_type = resp
+                .headers()
+                .get("Content-Type")
+                .and_then(|content_type| content_type.to_str().ok())
+                .and_then(|content_type| content_type.split(';').next())
+                .unwrap_or("application/octet-stream")
+                .trim()
+                .to_owned();
+
+            writer
+                .add_attachment(
+                    &attachment.numbered_file_name(i),
+                    &mime_type,
+                    resp.into_body(),
+                )
+                .await
+                .map_err(ScraperError::from)?;
+        }
 
-        Ok(get_scraper_constructor(resp.url())(
-            resp,
-            self.client.clone(),
-        ))
+        writer.finish().await.map_err(ScraperError::from)?;
+        Ok(too_large)
     }
 
     async fn get_response(&self) -> Result<Arc<BufferedResponse>, reqwest::Error> {
@@ -65,13 +164,13 @@ impl Volume {
         }
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %self.name, url = %self.url)))]
     async fn gen_response(&self) -> Result<(), reqwest::Error> {
         if self.resp.get().is_none() {
             self.resp
                 .set(Arc::new(
                     LTIForm::follow(
-                        BufferedResponse::new(self.client.get(self.url.clone()).send().await?)
-                            .await?,
+                        self.client.send(self.client.get(self.url.clone())).await?,
                         &self.client,
                     )
                     .await?,
@@ -2,11 +2,36 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum ScraperError {
+    #[error(
+        "No scraper for '{0}'.\n\
+        Please open a github issue with the book you tried downloading and with the url in this error message."
+    )]
+    // TODO add github issue template and insert link to open new 'Scraper not implemented' issue
+    UnsupportedProvider(reqwest::Url),
+
     #[error(transparent)]
     PdfError(#[from] lopdf::Error),
 
+    #[error("Malformed svg: {0}")]
+    MalformedSvg(#[from] svg2pdf::usvg::Error),
+
+    #[error("Failed to convert an svg to a pdf: {0}")]
+    SvgConversion(svg2pdf::ConversionError),
+
+    #[error("No valid Content-Type specified for downloaded content: {0}")]
+    MissingContentType(reqwest::Url),
+
+    #[error("Unexpected viewer at '{0}': {1}")]
+    UnexpectedViewer(reqwest::Url, &'static str),
+
+    #[error("Downloaded page image is not a valid image: {0}")]
+    InvalidImage(reqwest::Url),
+
     #[error(transparent)]
     Request(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
 #[derive(Error, Debug)]
@@ -14,8 +39,35 @@ pub enum LoginError {
     #[error("Your login information was invalid")]
     BadLogin,
 
+    #[error("The session was logged out")]
+    LoggedOut,
+
     #[error(transparent)]
     Reqwest(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Credentials(#[from] CredentialError),
+}
+
+#[derive(Error, Debug)]
+pub enum CredentialError {
+    #[error("The environment variable '{0}' is not set")]
+    Missing(String),
+
+    #[error("The credential file '{}' is accessible by other users", .0.display())]
+    InsecurePermissions(std::path::PathBuf),
+
+    #[error("The credential file '{}' doesn't contain an email and a password", .0.display())]
+    Malformed(std::path::PathBuf),
+
+    #[error("The login was cancelled")]
+    Cancelled,
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
 #[derive(Error, Debug)]
@@ -28,4 +80,7 @@ pub enum DigiDownloadError {
 
     #[error(transparent)]
     Request(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
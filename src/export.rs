@@ -0,0 +1,313 @@
+use crate::digi4school::book::Book;
+use crate::digi4school::session::Session;
+use crate::digi4school::volume::Volume;
+use crate::error::DigiDownloadError;
+use crate::output_profile::OutputProfile;
+use crate::trace_event;
+use crate::util::sanitize_file_name;
+use getset::{CopyGetters, Getters};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::BufWriter;
+
+/// Configures `export_library`.
+#[derive(Debug, Clone, Getters, CopyGetters)]
+pub struct ExportOptions {
+    /// Path of every exported volume relative to the export directory.
+    /// `/` separates directories. Available placeholders:
+    /// - `{year}`: year the book was redeemed in
+    /// - `{title}`: title of the book
+    /// - `{volume}`: name of the volume (the book title for books with only one volume)
+    /// - `{index}`: position of the volume in the book, starting at 01
+    /// - `{id}`: digi4school id of the book
+    #[getset(get = "pub")]
+    template: String,
+    #[getset(get_copy = "pub")]
+    profile: OutputProfile,
+    /// Doesn't download volumes whose file already exists.
+    #[getset(get_copy = "pub")]
+    skip_existing: bool,
+}
+
+impl ExportOptions {
+    pub const DEFAULT_TEMPLATE: &'static str = "{year}/{title}/{index} {volume}.pdf";
+
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = template.into();
+        self
+    }
+
+    pub fn with_profile(mut self, profile: OutputProfile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn with_skip_existing(mut self, skip_existing: bool) -> Self {
+        self.skip_existing = skip_existing;
+        self
+    }
+
+    /// Builds the path of a volume relative to the export directory.
+    /// Every placeholder value is made safe to use in a file name, so titles can't create extra directories.
+    pub(crate) fn render_path(&self, book: &Book, volume: &Volume, index: usize) -> PathBuf {
+        self.template
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .map(|component| {
+                let component = component
+                    .replace("{year}", &book.year().to_string())
+                    .replace("{title}", &sanitize_file_name(book.title()))
+                    .replace("{volume}", &sanitize_file_name(volume.name()))
+                    .replace("{index}", &format!("{:02}", index + 1))
+                    .replace("{id}", &book.id().to_string());
+
+                sanitize_file_name(&component)
+            })
+            .collect()
+    }
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            template: Self::DEFAULT_TEMPLATE.to_string(),
+            profile: OutputProfile::default(),
+            skip_existing: true,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportFailure {
+    pub book: String,
+    /// `None` if the volumes of the book couldn't be listed.
+    pub volume: Option<String>,
+    pub error: DigiDownloadError,
+}
+
+/// Summary of `export_library`.
+#[derive(Debug, Default, Getters)]
+#[getset(get = "pub")]
+pub struct ExportReport {
+    exported: Vec<PathBuf>,
+    /// Volumes that weren't downloaded because their file already exists.
+    skipped: Vec<PathBuf>,
+    failed: Vec<ExportFailure>,
+}
+
+impl ExportReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    pub(crate) fn merge(&mut self, other: Self) {
+        self.exported.extend(other.exported);
+        self.skipped.extend(other.skipped);
+        self.failed.extend(other.failed);
+    }
+
+    pub(crate) fn add_failure(&mut self, failure: ExportFailure) {
+        self.failed.push(failure);
+    }
+}
+
+impl Display for ExportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} exported, {} skipped, {} failed",
+            self.exported.len(),
+            self.skipped.len(),
+            self.failed.len()
+        )?;
+
+        for failure in &self.failed {
+            match &failure.volume {
+                Some(volume) => writeln!(f, "- {} / {}: {}", failure.book, volume, failure.error)?,
+                None => writeln!(f, "- {}: {}", failure.book, failure.error)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Downloads every volume of every book of the account into `dir`.
+/// A failing book or volume doesn't stop the export, it is listed in the report instead.
+///
+/// Files are first written with a `.part` extension and renamed once complete,
+/// so interrupted downloads aren't mistaken for existing files.
+pub async fn export_library(
+    session: &Session,
+    dir: &Path,
+    options: &ExportOptions,
+) -> Result<ExportReport, DigiDownloadError> {
+    let mut report = ExportReport::default();
+
+    for book in session.get_books().await? {
+        export_book(&book, dir, options, &mut report).await?;
+    }
+
+    Ok(report)
+}
+
+/// Exports all volumes of one book, failures are added to `report`.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(book.id = book.id(), book.title = %book.title())))]
+pub(crate) async fn export_book(
+    book: &Book,
+    dir: &Path,
+    options: &ExportOptions,
+    report: &mut ExportReport,
+) -> Result<(), DigiDownloadError> {
+    let volumes = match book.get_volumes().await {
+        Ok(volumes) => volumes,
+        Err(error) => {
+            trace_event!(WARN, %error, "failed to list volumes");
+            report.failed.push(ExportFailure {
+                book: book.to_string(),
+                volume: None,
+                error: error.into(),
+            });
+            return Ok(());
+        }
+    };
+
+    for (index, volume) in volumes.iter().enumerate() {
+        let path = dir.join(options.render_path(book, volume, index));
+
+        if options.skip_existing && exists(&path).await {
+            report.skipped.push(path);
+            continue;
+        }
+
+        match export_volume(book, volume, &path, options.profile()).await {
+            Ok(()) => report.exported.push(path),
+            Err(error) => {
+                trace_event!(WARN, volume = %volume.name(), %error, "failed to export volume");
+                report.failed.push(ExportFailure {
+                    book: book.to_string(),
+                    volume: Some(volume.name().clone()),
+                    error,
+                })
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Also saves the `Manifest` of the volume.
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume.name(), path = %path.display())))]
+pub(crate) async fn export_volume(
+    book: &Book,
+    volume: &Volume,
+    path: &Path,
+    profile: OutputProfile,
+) -> Result<(), DigiDownloadError> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let part_path = &part_path(path);
+
+    let result = async {
+        let mut file = BufWriter::new(File::create(part_path).await?);
+        let manifest = volume
+            .get_scraper()
+            .await?
+            .download_book_to(&mut file, &profile)
+            .await?;
+        Ok::<_, DigiDownloadError>(manifest.with_volume(volume))
+    }
+    .await;
+
+    if result.is_err() {
+        // an incomplete file is useless, the error is what matters
+        let _ = tokio::fs::remove_file(part_path).await;
+    }
+    let manifest = result?;
+
+    tokio::fs::rename(part_path, path).await?;
+    book.save_manifest(path, vec![manifest]).await?;
+    Ok(())
+}
+
+/// A file that can't be checked is treated as missing, the download will report the actual problem.
+async fn exists(path: &Path) -> bool {
+    tokio::fs::try_exists(path)
+        .await
+        .inspect_err(|_error| {
+            trace_event!(WARN, error = %_error, path = %path.display(), "failed to check if the file exists");
+        })
+        .unwrap_or(false)
+}
+
+/// Path files are written to until they are complete.
+pub(crate) fn part_path(path: &Path) -> PathBuf {
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(".part");
+    part_path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpClient;
+    use reqwest::{Client, Url};
+    use std::sync::Arc;
+
+    fn book_and_volume(title: &str, volume: &str) -> (Book, Volume) {
+        let client = Arc::new(HttpClient::new(Client::new()));
+        let url = Url::parse("https://a.digi4school.at/ebook/1234").unwrap();
+        (
+            Book::new(1234, 2030, url.clone(), title, client.clone()),
+            Volume::new(url.clone(), volume, url, client),
+        )
+    }
+
+    #[test]
+    fn renders_default_template() {
+        let (book, volume) = book_and_volume("Mathematik 5", "Arbeitsheft");
+        let path = ExportOptions::default().render_path(&book, &volume, 1);
+
+        let expected: PathBuf = [
+            book.year().to_string(),
+            "Mathematik 5".into(),
+            "02 Arbeitsheft.pdf".into(),
+        ]
+        .iter()
+        .collect();
+        assert_eq!(path, expected);
+    }
+
+    #[test]
+    fn placeholders_cannot_create_directories() {
+        let (book, volume) = book_and_volume("Deutsch/Englisch", "..");
+        let path = ExportOptions::default()
+            .with_template("{id}/{title}/{volume}")
+            .render_path(&book, &volume, 0);
+
+        let expected: PathBuf = ["1234", "Deutsch_Englisch", "_"].iter().collect();
+        assert_eq!(path, expected);
+    }
+
+    #[test]
+    fn empty_components_are_dropped() {
+        let (book, volume) = book_and_volume("Physik", "Physik");
+        let path = ExportOptions::default()
+            .with_template("/{title}//{index}.pdf")
+            .render_path(&book, &volume, 0);
+
+        let expected: PathBuf = ["Physik", "01.pdf"].iter().collect();
+        assert_eq!(path, expected);
+    }
+
+    #[test]
+    fn part_path_appends_extension() {
+        assert_eq!(
+            part_path(Path::new("books/maths.pdf")),
+            Path::new("books/maths.pdf.part")
+        );
+    }
+}
@@ -0,0 +1,593 @@
+use crate::http::replay::RecordedResponse;
+use crate::regex;
+use crate::util::format_date_time;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE, LOCATION};
+use reqwest::{Method, Request, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Replaces credentials and session cookies in recorded traffic.
+const REDACTED: &str = "<redacted>";
+
+/// Headers which authenticate the session.
+const SENSITIVE_HEADERS: [&str; 4] = [
+    "cookie",
+    "set-cookie",
+    "authorization",
+    "proxy-authorization",
+];
+
+/// Form fields and query parameters of the login and the LTI launch of books.
+const SENSITIVE_FIELDS: [&str; 5] = [
+    "email",
+    "password",
+    "oauth_signature",
+    "oauth_nonce",
+    "user_id",
+];
+
+/// Prefixes of LTI launch fields with personal data, e.g. `lis_person_name_full`.
+const SENSITIVE_FIELD_PREFIXES: [&str; 2] = ["lis_person_", "custom_"];
+
+/// Records the traffic of a session as an [HTTP Archive](http://www.softwareishard.com/blog/har-12-spec/),
+/// which can be opened in the network tab of browsers.
+/// Attach it to bug reports about scrapers, it replaces routing the traffic through a proxy.
+///
+/// The email, the password, all cookies, tokens in URLs and the personal fields of the LTI launch are redacted,
+/// but the pages of the books and the name of the account are recorded as they are served.
+/// Requests with redacted URLs can't be replayed.
+#[derive(Debug, Default)]
+pub struct HarRecorder {
+    entries: Mutex<Vec<HarEntry>>,
+}
+
+impl HarRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of recorded requests.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Everything recorded so far.
+    pub fn to_har(&self) -> Har {
+        Har {
+            log: HarLog {
+                version: "1.2".to_string(),
+                creator: HarCreator {
+                    name: env!("CARGO_PKG_NAME").to_string(),
+                    version: env!("CARGO_PKG_VERSION").to_string(),
+                },
+                entries: self.entries.lock().unwrap().clone(),
+            },
+        }
+    }
+
+    /// Writes everything recorded so far to `path`.
+    pub async fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        self.to_har().save(path).await
+    }
+
+    /// Requests are recorded before they are sent, because sending consumes their body.
+    pub(crate) fn request(&self, request: &Request) -> HarRequest {
+        let body = request.body().and_then(|body| body.as_bytes());
+        let mime_type = header(request.headers(), CONTENT_TYPE.as_str());
+
+        let url = redact_url(request.url());
+
+        HarRequest {
+            method: request.method().to_string(),
+            http_version: format!("{:?}", request.version()),
+            cookies: Vec::new(),
+            headers: headers(request.headers(), request.url()),
+            query_string: url
+                .query_pairs()
+                .map(|(name, value)| HarNameValue::new(&name, &value))
+                .collect(),
+            url: url.to_string(),
+            post_data: body.map(|body| HarPostData {
+                text: match mime_type.starts_with("application/x-www-form-urlencoded") {
+                    true => redact_form(body),
+                    false => String::from_utf8_lossy(body).into_owned(),
+                },
+                mime_type,
+            }),
+            headers_size: -1,
+            body_size: body.map_or(0, |body| body.len() as i64),
+        }
+    }
+
+    /// `result` is the response with its body (`None` if it wasn't downloaded) or why the request failed.
+    pub(crate) fn record(
+        &self,
+        request: HarRequest,
+        started: SystemTime,
+        time: Duration,
+        result: Result<(&Response, Option<&[u8]>), &reqwest::Error>,
+    ) {
+        let response = match result {
+            Ok((resp, body)) => HarResponse {
+                status: resp.status().as_u16(),
+                status_text: resp
+                    .status()
+                    .canonical_reason()
+                    .unwrap_or_default()
+                    .to_string(),
+                http_version: format!("{:?}", resp.version()),
+                cookies: Vec::new(),
+                headers: headers(resp.headers(), resp.url()),
+                content: HarContent::new(header(resp.headers(), CONTENT_TYPE.as_str()), body),
+                redirect_url: redact_location(&header(resp.headers(), "location"), resp.url()),
+                headers_size: -1,
+                body_size: body.map_or(-1, |body| body.len() as i64),
+                url: Some(redact_url(resp.url()).to_string()),
+                error: None,
+            },
+            // browsers record failed requests with status 0 as well
+            Err(e) => HarResponse {
+                status: 0,
+                status_text: String::new(),
+                http_version: String::new(),
+                cookies: Vec::new(),
+                headers: Vec::new(),
+                content: HarContent::new(String::new(), None),
+                redirect_url: String::new(),
+                headers_size: -1,
+                body_size: -1,
+                url: None,
+                error: Some(e.to_string()),
+            },
+        };
+
+        let time = time.as_secs_f64() * 1000.0;
+        self.entries.lock().unwrap().push(HarEntry {
+            started_date_time: format_date_time(started),
+            time,
+            request,
+            response,
+            cache: serde_json::Map::new(),
+            timings: HarTimings {
+                send: 0.0,
+                wait: time,
+                receive: 0.0,
+            },
+        });
+    }
+}
+
+/// A recorded HTTP Archive, see `HarRecorder`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Har {
+    log: HarLog,
+}
+
+impl Har {
+    /// Number of recorded requests.
+    pub fn len(&self) -> usize {
+        self.log.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.log.entries.is_empty()
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<(), std::io::Error> {
+        tokio::fs::write(path, serde_json::to_vec_pretty(self)?).await
+    }
+
+    /// Reads a file written by `save` or exported from the network tab of a browser.
+    pub async fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let buf = tokio::fs::read(path).await?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    /// The recorded responses keyed by their request, in the order they were recorded.
+    /// Failed requests and entries with an invalid method, URL or status are skipped.
+    pub(crate) fn into_responses(self) -> impl Iterator<Item = (Method, Url, RecordedResponse)> {
+        self.log.entries.into_iter().filter_map(|entry| {
+            let response = entry.response;
+            if response.error.is_some() {
+                return None;
+            }
+
+            let mut headers = HeaderMap::new();
+            for header in response.headers {
+                // HTTP/2 pseudo headers like `:status` are recorded by browsers
+                if let (Ok(name), Ok(value)) = (
+                    HeaderName::from_bytes(header.name.as_bytes()),
+                    HeaderValue::from_str(&header.value),
+                ) {
+                    headers.append(name, value);
+                }
+            }
+
+            let content = response.content;
+            let body = match (content.text, content.encoding.as_deref()) {
+                (Some(text), Some("base64")) => BASE64_STANDARD.decode(text).ok()?,
+                (Some(text), _) => text.into_bytes(),
+                (None, _) => Vec::new(),
+            };
+
+            Some((
+                Method::from_bytes(entry.request.method.as_bytes()).ok()?,
+                Url::parse(&entry.request.url).ok()?,
+                RecordedResponse {
+                    status: StatusCode::from_u16(response.status).ok()?,
+                    headers,
+                    body,
+                    url: response.url.and_then(|url| Url::parse(&url).ok()),
+                    redirect_url: Some(response.redirect_url).filter(|url| !url.is_empty()),
+                },
+            ))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct HarLog {
+    version: String,
+    creator: HarCreator,
+    entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct HarCreator {
+    name: String,
+    version: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct HarEntry {
+    started_date_time: String,
+    /// Milliseconds.
+    time: f64,
+    request: HarRequest,
+    response: HarResponse,
+    cache: serde_json::Map<String, serde_json::Value>,
+    timings: HarTimings,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub(crate) struct HarRequest {
+    method: String,
+    url: String,
+    http_version: String,
+    /// Always empty, cookies are redacted.
+    cookies: Vec<serde_json::Value>,
+    headers: Vec<HarNameValue>,
+    query_string: Vec<HarNameValue>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    post_data: Option<HarPostData>,
+    headers_size: i64,
+    body_size: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct HarResponse {
+    status: u16,
+    status_text: String,
+    http_version: String,
+    /// Always empty, cookies are redacted.
+    cookies: Vec<serde_json::Value>,
+    headers: Vec<HarNameValue>,
+    content: HarContent,
+    #[serde(rename = "redirectURL")]
+    redirect_url: String,
+    headers_size: i64,
+    body_size: i64,
+    /// URL of the response after following all redirects, which the scrapers depend on.
+    #[serde(rename = "_url", skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    /// Why the request failed, if it did.
+    #[serde(rename = "_error", skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct HarNameValue {
+    name: String,
+    value: String,
+}
+
+impl HarNameValue {
+    fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct HarPostData {
+    mime_type: String,
+    text: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct HarContent {
+    size: i64,
+    mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    /// `base64` for binary bodies like images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
+}
+
+impl HarContent {
+    fn new(mime_type: String, body: Option<&[u8]>) -> Self {
+        let (text, encoding) = match body.map(std::str::from_utf8) {
+            // pages like the LTI launch form contain the personal data of the account
+            Some(Ok(text)) if mime_type.contains("html") => (Some(redact_html(text)), None),
+            Some(Ok(text)) => (Some(text.to_string()), None),
+            Some(Err(_)) => (
+                Some(BASE64_STANDARD.encode(body.unwrap())),
+                Some("base64".to_string()),
+            ),
+            None => (None, None),
+        };
+
+        Self {
+            size: body.map_or(-1, |body| body.len() as i64),
+            mime_type,
+            text,
+            encoding,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct HarTimings {
+    send: f64,
+    wait: f64,
+    receive: f64,
+}
+
+fn header(headers: &HeaderMap, name: &str) -> String {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// `url` is the URL relative locations are resolved against.
+fn headers(headers: &HeaderMap, url: &Url) -> Vec<HarNameValue> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = match SENSITIVE_HEADERS.contains(&name.as_str()) {
+                true => REDACTED.into(),
+                false if name == LOCATION => {
+                    redact_location(&String::from_utf8_lossy(value.as_bytes()), url).into()
+                }
+                false => String::from_utf8_lossy(value.as_bytes()),
+            };
+            HarNameValue::new(name.as_str(), &value)
+        })
+        .collect()
+}
+
+fn redact_form(body: &[u8]) -> String {
+    let Ok(fields) = serde_urlencoded::from_bytes::<Vec<(String, String)>>(body) else {
+        // better lose the body than leak the password
+        return REDACTED.to_string();
+    };
+
+    let fields: Vec<(String, String)> = fields
+        .into_iter()
+        .map(|(name, value)| match is_sensitive(&name) {
+            true => (name, REDACTED.to_string()),
+            false => (name, value),
+        })
+        .collect();
+
+    serde_urlencoded::to_string(fields).unwrap()
+}
+
+/// Whether a form field or query parameter identifies the account or authenticates requests.
+fn is_sensitive(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SENSITIVE_FIELDS.contains(&name.as_str())
+        || SENSITIVE_FIELD_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        || name.contains("token")
+}
+
+fn redact_url(url: &Url) -> Url {
+    if !url.query_pairs().any(|(name, _)| is_sensitive(&name)) {
+        return url.clone();
+    }
+
+    let mut redacted = url.clone();
+    redacted
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(
+            url.query_pairs()
+                .map(|(name, value)| match is_sensitive(&name) {
+                    true => (name, REDACTED.into()),
+                    false => (name, value),
+                }),
+        );
+    redacted
+}
+
+/// Relative locations are resolved against `base`, but only written as absolute URLs if they had to be redacted.
+fn redact_location(location: &str, base: &Url) -> String {
+    match base.join(location) {
+        Ok(url) if url.query_pairs().any(|(name, _)| is_sensitive(&name)) => {
+            redact_url(&url).to_string()
+        }
+        _ => location.to_string(),
+    }
+}
+
+/// Replaces the values of sensitive `<input>` fields, e.g. the hidden fields of the LTI launch form.
+fn redact_html(html: &str) -> String {
+    regex!(r"(?is)<input\b[^>]*>")
+        .replace_all(html, |input: &regex::Captures| {
+            let tag = &input[0];
+            let sensitive = regex!(r#"(?i)\sname\s*=\s*["']?([^"'\s>]+)"#)
+                .captures(tag)
+                .is_some_and(|name| is_sensitive(&name[1]));
+            if !sensitive {
+                return tag.to_string();
+            }
+
+            regex!(r#"(?i)(\svalue\s*=\s*)("[^"]*"|'[^']*'|[^\s>]+)"#)
+                .replace(tag, format!("${{1}}\"{REDACTED}\""))
+                .into_owned()
+        })
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffered_response::tests::response;
+
+    #[test]
+    fn redacts_sensitive_fields() {
+        let form = redact_form(
+            b"email=a%40b.at&password=secret&oauth_signature=sig&oauth_nonce=1&user_id=42\
+              &lis_person_name_full=Max&custom_school=HTL&resource_link_id=123",
+        );
+        assert_eq!(
+            form,
+            "email=%3Credacted%3E&password=%3Credacted%3E&oauth_signature=%3Credacted%3E\
+             &oauth_nonce=%3Credacted%3E&user_id=%3Credacted%3E&lis_person_name_full=%3Credacted%3E\
+             &custom_school=%3Credacted%3E&resource_link_id=123"
+        );
+    }
+
+    #[test]
+    fn redacts_tokens_in_urls() {
+        let url = Url::parse("https://a.digi4school.at/ebook/123?token=abc&page=2").unwrap();
+        assert_eq!(
+            redact_url(&url).as_str(),
+            "https://a.digi4school.at/ebook/123?token=%3Credacted%3E&page=2"
+        );
+
+        let url = Url::parse("https://a.digi4school.at/ebook/123?page=2").unwrap();
+        assert_eq!(redact_url(&url), url);
+        let base = Url::parse("https://digi4school.at/ebooks").unwrap();
+        assert_eq!(
+            redact_location("/login?token=abc", &base),
+            "https://digi4school.at/login?token=%3Credacted%3E"
+        );
+        assert_eq!(redact_location("/login?page=2", &base), "/login?page=2");
+    }
+
+    #[tokio::test]
+    async fn round_trips_through_json() {
+        let recorder = HarRecorder::new();
+        let request = reqwest::Client::new()
+            .post("https://digi4school.at/br/xhr/login?access_token=abc")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body("email=a%40b.at&password=secret")
+            .build()
+            .unwrap();
+        let resp = response("text/plain", "OK").await;
+
+        let recorded = recorder.request(&request);
+        recorder.record(
+            recorded,
+            SystemTime::now(),
+            Duration::from_millis(20),
+            Ok((&resp, Some(resp.bytes()))),
+        );
+        let image = response("image/png", vec![0x89, b'P', b'N', b'G', 0xff]).await;
+        let recorded = recorder.request(
+            &reqwest::Client::new()
+                .get("https://a.digi4school.at/ebook/123/1.png")
+                .build()
+                .unwrap(),
+        );
+        recorder.record(
+            recorded,
+            SystemTime::now(),
+            Duration::from_millis(5),
+            Ok((&image, Some(image.bytes()))),
+        );
+
+        let json = serde_json::to_string(&recorder.to_har()).unwrap();
+        assert!(!json.contains("secret"));
+        assert!(!json.contains("abc"));
+
+        let har: Har = serde_json::from_str(&json).unwrap();
+        assert_eq!(har.len(), 2);
+        let responses: Vec<_> = har.into_responses().collect();
+
+        let (method, url, login) = &responses[0];
+        assert_eq!(method, Method::POST);
+        assert_eq!(
+            url.as_str(),
+            "https://digi4school.at/br/xhr/login?access_token=%3Credacted%3E"
+        );
+        assert_eq!(login.status, StatusCode::OK);
+        assert_eq!(login.body, b"OK");
+
+        let (method, url, image) = &responses[1];
+        assert_eq!(method, Method::GET);
+        assert_eq!(url.as_str(), "https://a.digi4school.at/ebook/123/1.png");
+        assert_eq!(image.body, vec![0x89, b'P', b'N', b'G', 0xff]);
+        assert_eq!(image.headers[CONTENT_TYPE], "image/png");
+    }
+
+    #[tokio::test]
+    async fn redacts_the_launch_form_in_responses() {
+        let recorder = HarRecorder::new();
+        let request = reqwest::Client::new()
+            .get("https://digi4school.at/ebook/123")
+            .build()
+            .unwrap();
+        let form = r#"<form name="ltiLaunchForm" id="lti" method="post" action="https://a.digi4school.at/lti">
+            <input type="hidden" name="resource_link_id" value="123">
+            <input type="hidden" name="user_id" value="4711">
+            <input type="hidden" value='Max Mustermann' name="lis_person_name_full">
+            <input type="hidden" name="custom_school" value=HTL>
+            <input type="hidden" name="oauth_signature" value="c2lnbmF0dXJl">
+        </form>"#;
+        let resp = response("text/html; charset=utf-8", form).await;
+
+        recorder.record(
+            recorder.request(&request),
+            SystemTime::now(),
+            Duration::from_millis(20),
+            Ok((&resp, Some(resp.bytes()))),
+        );
+
+        let json = serde_json::to_string(&recorder.to_har()).unwrap();
+        for secret in ["4711", "Max Mustermann", "HTL", "c2lnbmF0dXJl"] {
+            assert!(!json.contains(secret), "{secret} wasn't redacted");
+        }
+        assert!(json.contains(r#"name=\"resource_link_id\" value=\"123\""#));
+        assert!(json.contains(r#"name=\"user_id\" value=\"<redacted>\""#));
+    }
+}
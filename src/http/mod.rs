@@ -0,0 +1,127 @@
+pub mod har;
+pub mod replay;
+mod transport;
+
+pub use transport::{Middleware, Next, Transport};
+
+use crate::buffered_response::BufferedResponse;
+use crate::http::har::HarRecorder;
+use reqwest::{Client, IntoUrl, Method, Request, RequestBuilder, Response};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+
+/// The client all requests of a session go through.
+/// Requests are built like with reqwest, but sent with `HttpClient::send`,
+/// which passes them through the middleware to the transport
+/// and hands them to the `HarRecorder`, if the session has one.
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    /// Only builds the requests if another transport is used.
+    client: Client,
+    transport: Arc<dyn Transport>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    recorder: Option<Arc<HarRecorder>>,
+}
+
+impl HttpClient {
+    pub fn new(client: Client) -> Self {
+        Self {
+            transport: Arc::new(client.clone()),
+            client,
+            middleware: Vec::new(),
+            recorder: None,
+        }
+    }
+
+    /// Sends the requests with `transport` instead of the reqwest client.
+    /// The reqwest client still builds the requests, but its cookie store only sees the requests it sends,
+    /// use a middleware to handle cookies (sessions add one themselves).
+    pub fn with_transport(self, transport: Arc<dyn Transport>) -> Self {
+        Self { transport, ..self }
+    }
+
+    /// Adds `middleware` after the already added ones, see `Middleware`.
+    pub fn with_middleware(mut self, middleware: Arc<dyn Middleware>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Records every request sent through this client, as returned by the middleware.
+    pub fn with_recorder(self, recorder: Arc<HarRecorder>) -> Self {
+        Self {
+            recorder: Some(recorder),
+            ..self
+        }
+    }
+
+    pub fn recorder(&self) -> Option<&Arc<HarRecorder>> {
+        self.recorder.as_ref()
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// Sends the request and downloads the whole body.
+    pub async fn send(&self, request: RequestBuilder) -> Result<BufferedResponse, reqwest::Error> {
+        Ok(self
+            .send_limited(request, u64::MAX)
+            .await?
+            .expect("no body is larger than u64::MAX"))
+    }
+
+    /// Like `send`, but `None` if the body is larger than `max_size` bytes,
+    /// see `BufferedResponse::with_max_size`.
+    pub async fn send_limited(
+        &self,
+        request: RequestBuilder,
+        max_size: u64,
+    ) -> Result<Option<BufferedResponse>, reqwest::Error> {
+        let request = request.build()?;
+        let Some(recorder) = &self.recorder else {
+            let resp = self.execute(request).await?;
+            return BufferedResponse::with_max_size(resp, max_size).await;
+        };
+
+        let recorded = recorder.request(&request);
+        let started = SystemTime::now();
+        let start = Instant::now();
+
+        let result = match self.execute(request).await {
+            Ok(resp) => BufferedResponse::read_limited(resp, max_size).await,
+            Err(e) => Err(e),
+        };
+
+        recorder.record(
+            recorded,
+            started,
+            start.elapsed(),
+            match &result {
+                Ok(Ok(resp)) => Ok((resp, Some(resp.bytes()))),
+                Ok(Err(too_large)) => Ok((too_large, None)),
+                Err(e) => Err(e),
+            },
+        );
+        result.map(Result::ok)
+    }
+
+    async fn execute(&self, request: Request) -> Result<Response, reqwest::Error> {
+        Next::new(&self.middleware, self.transport.as_ref())
+            .run(request)
+            .await
+    }
+}
+
+impl Default for HttpClient {
+    fn default() -> Self {
+        Self::new(Client::new())
+    }
+}
@@ -0,0 +1,369 @@
+use crate::http::har::Har;
+use crate::http::Transport;
+use crate::trace_event;
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE, LOCATION};
+use reqwest::{Method, Request, Response, ResponseBuilderExt, StatusCode, Url};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Serves recorded responses instead of sending requests to the network,
+/// e.g. to reproduce a bug from a `HarRecorder` file or to test against known pages.
+///
+/// Requests are matched by their method and URL, bodies and headers are ignored.
+/// A request sent multiple times gets the recorded responses in order, the last one is repeated.
+/// Recorded redirects (as exported by browsers) are followed, endless ones end with a `508 Loop Detected`.
+/// Requests that weren't recorded get an empty `404 Not Found`.
+///
+/// Use it as the `Transport` of an `HttpClient`, or with `Session::replay`.
+#[derive(Debug, Default)]
+pub struct Replay {
+    responses: HashMap<(Method, Url), Vec<RecordedResponse>>,
+    /// Number of times each request was served.
+    served: Mutex<HashMap<(Method, Url), usize>>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedResponse {
+    pub(crate) status: StatusCode,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Vec<u8>,
+    /// URL after following all redirects, if it was recorded.
+    pub(crate) url: Option<Url>,
+    /// Target of a recorded redirect.
+    pub(crate) redirect_url: Option<String>,
+}
+
+impl Replay {
+    /// Redirects followed for a single request, like reqwest's default policy.
+    const MAX_REDIRECTS: usize = 10;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_har(har: Har) -> Self {
+        let mut replay = Self::new();
+        replay.add_har(har);
+        replay
+    }
+
+    /// Reads a HAR file, or a fixture directory.
+    ///
+    /// In a fixture directory every `.har` file is added in the order of their names,
+    /// every other file is served for `GET` requests to `https://{host}/{path}`,
+    /// where `host` is the first directory below `dir` and `path` the rest of its path.
+    /// Files without an extension are served as HTML.
+    pub async fn load(path: &Path) -> Result<Self, std::io::Error> {
+        if !tokio::fs::metadata(path).await?.is_dir() {
+            return Ok(Self::from_har(Har::load(path).await?));
+        }
+
+        let mut replay = Self::new();
+        for file in Self::list_files(path).await? {
+            let relative = file.strip_prefix(path).unwrap();
+
+            if file.extension().is_some_and(|extension| extension == "har") {
+                replay.add_har(Har::load(&file).await?);
+                continue;
+            }
+
+            let segments: Vec<String> = relative
+                .iter()
+                .map(|segment| segment.to_string_lossy().into_owned())
+                .collect();
+            let Ok(url) = Url::parse(&format!("https://{}", segments.join("/"))) else {
+                continue;
+            };
+
+            replay.add_response(
+                Method::GET,
+                url,
+                StatusCode::OK,
+                mime_type(&file),
+                tokio::fs::read(&file).await?,
+            );
+        }
+
+        Ok(replay)
+    }
+
+    pub fn add_har(&mut self, har: Har) {
+        for (method, url, response) in har.into_responses() {
+            self.responses
+                .entry((method, url))
+                .or_default()
+                .push(response);
+        }
+    }
+
+    /// Serves `body` for requests to `url`, after the responses added before for the same request.
+    pub fn add_response(
+        &mut self,
+        method: Method,
+        url: Url,
+        status: StatusCode,
+        content_type: &str,
+        body: impl Into<Vec<u8>>,
+    ) {
+        let mut headers = HeaderMap::new();
+        if let Ok(content_type) = HeaderValue::from_str(content_type) {
+            headers.insert(CONTENT_TYPE, content_type);
+        }
+
+        self.responses
+            .entry((method, url))
+            .or_default()
+            .push(RecordedResponse {
+                status,
+                headers,
+                body: body.into(),
+                url: None,
+                redirect_url: None,
+            });
+    }
+
+    /// Number of distinct requests that can be served.
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+
+    fn respond(&self, request: &Request) -> Response {
+        let mut method = request.method().clone();
+        let mut url = request.url().clone();
+
+        for _ in 0..=Self::MAX_REDIRECTS {
+            let Some(recorded) = self.next_response(&method, &url) else {
+                trace_event!(WARN, %method, %url, "request wasn't recorded");
+                return Self::build_response(
+                    StatusCode::NOT_FOUND,
+                    &HeaderMap::new(),
+                    Vec::new(),
+                    url,
+                );
+            };
+
+            let location = recorded
+                .redirect_url
+                .as_deref()
+                .or_else(|| recorded.headers.get(LOCATION)?.to_str().ok())
+                .filter(|_| recorded.status.is_redirection())
+                .and_then(|location| url.join(location).ok());
+
+            match location {
+                Some(location) => {
+                    // like browsers, only these keep the method
+                    if !matches!(
+                        recorded.status,
+                        StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT
+                    ) {
+                        method = Method::GET;
+                    }
+                    url = location;
+                }
+                None => {
+                    let url = recorded.url.unwrap_or(url);
+                    return Self::build_response(
+                        recorded.status,
+                        &recorded.headers,
+                        recorded.body,
+                        url,
+                    );
+                }
+            }
+        }
+
+        trace_event!(WARN, url = %request.url(), "too many recorded redirects");
+        Self::build_response(
+            StatusCode::LOOP_DETECTED,
+            &HeaderMap::new(),
+            Vec::new(),
+            url,
+        )
+    }
+
+    fn next_response(&self, method: &Method, url: &Url) -> Option<RecordedResponse> {
+        let key = (method.clone(), url.clone());
+        let responses = self.responses.get(&key)?;
+
+        let mut served = self.served.lock().unwrap();
+        let count = served.entry(key).or_default();
+        let response = responses.get(*count).unwrap_or(responses.last()?).clone();
+        *count += 1;
+
+        Some(response)
+    }
+
+    fn build_response(
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: Vec<u8>,
+        url: Url,
+    ) -> Response {
+        let mut builder = http::Response::builder().status(status).url(url);
+        for (name, value) in headers {
+            // the body is stored decoded
+            if name != "content-encoding" && name != "content-length" && name != "transfer-encoding"
+            {
+                builder = builder.header(name, value);
+            }
+        }
+
+        Response::from(builder.body(body).unwrap())
+    }
+
+    async fn list_files(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+
+        while let Some(dir) = dirs.pop() {
+            let mut entries = tokio::fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                match entry.file_type().await?.is_dir() {
+                    true => dirs.push(entry.path()),
+                    false => files.push(entry.path()),
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+}
+
+#[async_trait]
+impl Transport for Replay {
+    async fn execute(&self, request: Request) -> Result<Response, reqwest::Error> {
+        Ok(self.respond(&request))
+    }
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        None | Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("pdf") => "application/pdf",
+        Some("json") => "application/json",
+        Some("js") => "text/javascript",
+        Some("css") => "text/css",
+        _ => "text/plain; charset=utf-8",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::digi4school::session::Session;
+    use crate::output_profile::OutputProfile;
+    use std::sync::Arc;
+
+    async fn get(replay: &Replay, url: &str) -> Response {
+        let request = reqwest::Client::new().get(url).build().unwrap();
+        replay.execute(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn replays_the_library_fixture() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/library");
+        let replay = Replay::load(&fixture).await.unwrap();
+        let session = Session::replay(Arc::new(replay)).await.unwrap();
+
+        let books = session.get_books().await.unwrap();
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].title(), "Mathematik 5");
+
+        let volumes = books[0].get_volumes().await.unwrap();
+        let names: Vec<&str> = volumes
+            .iter()
+            .map(|volume| volume.name().as_str())
+            .collect();
+        assert_eq!(names, ["Schulbuch", "Arbeitsheft"]);
+
+        let scraper = volumes[0].get_scraper().await.unwrap();
+        assert_eq!(scraper.fetch_page_count().await.unwrap(), 2);
+
+        let mut buf = Vec::new();
+        books[0]
+            .download_to(&mut buf, &OutputProfile::default())
+            .await
+            .unwrap();
+        let document = lopdf::Document::load_mem(&buf).unwrap();
+        let pages = document.get_pages();
+        assert_eq!(pages.len(), 3);
+
+        let toc: Vec<(usize, String, usize)> = document
+            .get_toc()
+            .unwrap()
+            .toc
+            .into_iter()
+            .map(|entry| (entry.level, entry.title, entry.page))
+            .collect();
+        assert!(toc.contains(&(2, "1 Zahlen".into(), 1)), "{toc:?}");
+        assert!(toc.contains(&(3, "1.1 Brüche".into(), 2)), "{toc:?}");
+
+        // the goToPage link of the first page
+        let annots = document
+            .get_dictionary(pages[&1])
+            .unwrap()
+            .get_deref(b"Annots", &document)
+            .and_then(lopdf::Object::as_array)
+            .unwrap();
+        let link = document
+            .dereference(&annots[0])
+            .unwrap()
+            .1
+            .as_dict()
+            .unwrap();
+        assert_eq!(link.get(b"Subtype").unwrap().as_name().unwrap(), b"Link");
+    }
+
+    #[tokio::test]
+    async fn serves_responses_in_order_and_repeats_the_last() {
+        let url = Url::parse("https://digi4school.at/ebooks").unwrap();
+        let mut replay = Replay::new();
+        replay.add_response(
+            Method::GET,
+            url.clone(),
+            StatusCode::OK,
+            "text/plain",
+            "first",
+        );
+        replay.add_response(Method::GET, url, StatusCode::OK, "text/plain", "second");
+
+        for expected in ["first", "second", "second"] {
+            let resp = get(&replay, "https://digi4school.at/ebooks").await;
+            assert_eq!(resp.text().await.unwrap(), expected);
+        }
+        let resp = get(&replay, "https://digi4school.at/other").await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn ends_redirect_loops() {
+        let url = Url::parse("https://digi4school.at/loop").unwrap();
+        let mut replay = Replay::new();
+        replay.add_response(
+            Method::GET,
+            url.clone(),
+            StatusCode::FOUND,
+            "text/plain",
+            "",
+        );
+        replay.responses.get_mut(&(Method::GET, url)).unwrap()[0].redirect_url =
+            Some("/loop".to_string());
+
+        let resp = get(&replay, "https://digi4school.at/loop").await;
+        assert_eq!(resp.status(), StatusCode::LOOP_DETECTED);
+    }
+}
@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+use reqwest::{Client, Request, Response};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Sends the requests of an `HttpClient`, by default a `reqwest::Client`.
+///
+/// Implement it to serve responses from somewhere else, e.g. a cache or a mock.
+/// reqwest's errors can't be constructed outside of reqwest,
+/// so failures of custom transports have to be expressed as responses (e.g. `502 Bad Gateway`).
+#[async_trait]
+pub trait Transport: Send + Sync + Debug {
+    async fn execute(&self, request: Request) -> Result<Response, reqwest::Error>;
+}
+
+#[async_trait]
+impl Transport for Client {
+    async fn execute(&self, request: Request) -> Result<Response, reqwest::Error> {
+        Client::execute(self, request).await
+    }
+}
+
+/// Sees every request of an `HttpClient` before it is sent and its response before it is read,
+/// e.g. to add headers, collect metrics or answer requests from a cache.
+///
+/// Middleware added first runs first, the last one hands the request to the `Transport`.
+#[async_trait]
+pub trait Middleware: Send + Sync + Debug {
+    /// Call `next.run(request)` to pass the request on, or return a response without doing so.
+    async fn handle(&self, request: Request, next: Next<'_>) -> Result<Response, reqwest::Error>;
+}
+
+/// The rest of the middleware stack, see `Middleware::handle`.
+/// It can be copied to send more than one request, e.g. to retry.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    transport: &'a dyn Transport,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(middleware: &'a [Arc<dyn Middleware>], transport: &'a dyn Transport) -> Self {
+        Self {
+            middleware,
+            transport,
+        }
+    }
+
+    pub async fn run(self, request: Request) -> Result<Response, reqwest::Error> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => {
+                middleware
+                    .handle(request, Next::new(rest, self.transport))
+                    .await
+            }
+            None => self.transport.execute(request).await,
+        }
+    }
+}
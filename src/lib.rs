@@ -1,9 +1,23 @@
+pub mod best_effort;
 pub mod digi4school;
 mod scraper;
 
 mod buffered_response;
 pub mod error;
+pub mod export;
+pub mod http;
+pub mod manifest;
+pub mod output_profile;
+pub mod page;
+pub mod page_size;
+pub mod sync;
 mod util;
 
+pub use buffered_response::BufferedResponse;
+pub use http::HttpClient;
 pub use lopdf;
-pub use scraper::merge_pdf;
+pub use scraper::{
+    merge_pdf, BaseScraper, PdfWriter, RasterScraper, Scraper, ScraperConstructor, ScraperRegistry,
+    SvgScraper,
+};
+pub use util::sanitize_file_name;
@@ -0,0 +1,489 @@
+use crate::best_effort::PageFailure;
+use crate::digi4school::book::Book;
+use crate::digi4school::volume::Volume;
+use crate::export::part_path;
+use crate::page::Page;
+use crate::util::{format_date_time, hex};
+use getset::{CopyGetters, Getters};
+use lopdf::Document;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tokio::io::AsyncReadExt;
+
+/// Describes what a downloaded PDF should contain.
+/// Saved as JSON next to the PDF by the download functions, see `Manifest::path`,
+/// so the file can later be checked with `verify`.
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct Manifest {
+    /// `None` if the file wasn't downloaded through a `Book`.
+    #[getset(get_copy = "pub")]
+    book_id: Option<u16>,
+    #[getset(get = "pub")]
+    book_title: Option<String>,
+    /// In the order they are written to the file.
+    #[getset(get = "pub")]
+    volumes: Vec<VolumeManifest>,
+    /// Name and version of the library that wrote the file.
+    #[getset(get = "pub")]
+    tool_version: String,
+    /// When the file was written, ISO 8601 in UTC.
+    #[getset(get = "pub")]
+    created_at: String,
+    /// SHA-256 of the whole file.
+    #[getset(get = "pub")]
+    sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct VolumeManifest {
+    #[getset(get = "pub")]
+    name: Option<String>,
+    #[getset(get = "pub")]
+    url: Option<String>,
+    /// Number of pages that should have been written,
+    /// all pages of the volume (see `Scraper::fetch_page_count`) unless only some were requested.
+    #[getset(get_copy = "pub")]
+    expected_page_count: u16,
+    /// Number of pages written to the file.
+    #[getset(get_copy = "pub")]
+    page_count: u16,
+    #[getset(get = "pub")]
+    pages: Vec<PageManifest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Getters, CopyGetters)]
+pub struct PageManifest {
+    /// One-based number of the page within the volume.
+    #[getset(get_copy = "pub")]
+    index: u16,
+    #[getset(get = "pub")]
+    label: Option<String>,
+    #[getset(get = "pub")]
+    source_url: Option<String>,
+    /// SHA-256 of the svg the page was converted from, only for pages served as svgs.
+    #[getset(get = "pub")]
+    source_sha256: Option<String>,
+    #[getset(get = "pub")]
+    resources: Vec<ResourceManifest>,
+    /// Set if the page was replaced in a best-effort download.
+    #[getset(get = "pub")]
+    failure: Option<PageFailure>,
+    /// See `Page::degraded`.
+    #[serde(default)]
+    #[getset(get_copy = "pub")]
+    degraded: bool,
+}
+
+/// A file referenced by a page, see `PageResource`.
+#[derive(Debug, Clone, Serialize, Deserialize, Getters)]
+#[getset(get = "pub")]
+pub struct ResourceManifest {
+    url: String,
+    sha256: String,
+}
+
+impl Manifest {
+    /// Describes the already written file at `pdf_path`.
+    pub async fn for_file(
+        pdf_path: &Path,
+        volumes: Vec<VolumeManifest>,
+    ) -> Result<Self, std::io::Error> {
+        Ok(Self {
+            book_id: None,
+            book_title: None,
+            volumes,
+            tool_version: concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).into(),
+            created_at: format_date_time(SystemTime::now()),
+            sha256: hash_file(pdf_path).await?,
+        })
+    }
+
+    pub fn with_book(self, book: &Book) -> Self {
+        Self {
+            book_id: Some(book.id()),
+            book_title: Some(book.title().clone()),
+            ..self
+        }
+    }
+
+    /// Where the manifest of `pdf_path` is stored, e.g. `maths.manifest.json` for `maths.pdf`.
+    pub fn path(pdf_path: &Path) -> PathBuf {
+        pdf_path.with_extension("manifest.json")
+    }
+
+    /// Loads the manifest of `pdf_path`.
+    pub async fn load(pdf_path: &Path) -> Result<Self, std::io::Error> {
+        let buf = tokio::fs::read(Self::path(pdf_path)).await?;
+        Ok(serde_json::from_slice(&buf)?)
+    }
+
+    /// Saves the manifest next to `pdf_path`.
+    pub async fn save(&self, pdf_path: &Path) -> Result<(), std::io::Error> {
+        let path = Self::path(pdf_path);
+        let part_path = part_path(&path);
+
+        tokio::fs::write(&part_path, serde_json::to_vec_pretty(self)?).await?;
+        tokio::fs::rename(part_path, path).await
+    }
+
+    /// Number of pages the file should have.
+    pub fn page_count(&self) -> usize {
+        self.volumes
+            .iter()
+            .map(|volume| usize::from(volume.page_count))
+            .sum()
+    }
+
+    /// Checks the file at `pdf_path` against this manifest.
+    pub async fn verify(&self, pdf_path: &Path) -> Result<Verification, std::io::Error> {
+        let mut problems = Vec::new();
+
+        let buf = tokio::fs::read(pdf_path).await?;
+
+        let sha256 = hash(&buf);
+        if sha256 != self.sha256 {
+            problems.push(Problem::ChecksumMismatch {
+                expected: self.sha256.clone(),
+                actual: sha256,
+            });
+        }
+
+        match Document::load_mem(&buf) {
+            Ok(document) if document.get_pages().len() != self.page_count() => {
+                problems.push(Problem::PageCountMismatch {
+                    expected: self.page_count(),
+                    actual: document.get_pages().len(),
+                })
+            }
+            Ok(_) => {}
+            Err(e) => problems.push(Problem::Unreadable(e.to_string())),
+        }
+
+        for (i, volume) in self.volumes.iter().enumerate() {
+            let name = || match &volume.name {
+                Some(name) => name.clone(),
+                None => format!("volume {}", i + 1),
+            };
+
+            if volume.page_count < volume.expected_page_count {
+                problems.push(Problem::IncompleteVolume {
+                    volume: name(),
+                    expected: volume.expected_page_count,
+                    actual: volume.page_count,
+                });
+            }
+
+            let replaced: Vec<u16> = volume
+                .pages
+                .iter()
+                .filter(|page| page.failure.is_some())
+                .map(|page| page.index)
+                .collect();
+            if !replaced.is_empty() {
+                problems.push(Problem::ReplacedPages {
+                    volume: name(),
+                    pages: replaced,
+                });
+            }
+
+            let degraded: Vec<u16> = volume
+                .pages
+                .iter()
+                .filter(|page| page.degraded)
+                .map(|page| page.index)
+                .collect();
+            if !degraded.is_empty() {
+                problems.push(Problem::DegradedPages {
+                    volume: name(),
+                    pages: degraded,
+                });
+            }
+        }
+
+        Ok(Verification { problems })
+    }
+}
+
+impl VolumeManifest {
+    pub fn new(expected_page_count: u16, pages: Vec<PageManifest>) -> Self {
+        Self {
+            name: None,
+            url: None,
+            expected_page_count,
+            // page numbers are u16, so only repeated pages could exceed it
+            page_count: u16::try_from(pages.len()).unwrap_or(u16::MAX),
+            pages,
+        }
+    }
+
+    /// Sets the name and URL of the volume.
+    pub fn with_volume(self, volume: &Volume) -> Self {
+        Self {
+            name: Some(volume.name().clone()),
+            url: Some(volume.url().to_string()),
+            ..self
+        }
+    }
+}
+
+impl PageManifest {
+    pub(crate) fn new(page: &Page, failure: Option<PageFailure>) -> Self {
+        Self {
+            index: page.index(),
+            label: page.label().clone(),
+            source_url: page.source_url().as_ref().map(Url::to_string),
+            source_sha256: page.raw_svg().as_ref().map(|svg| hash(svg.as_bytes())),
+            resources: page
+                .resources()
+                .iter()
+                .map(|resource| ResourceManifest {
+                    url: resource.url().to_string(),
+                    sha256: hash(resource.data()),
+                })
+                .collect(),
+            failure,
+            degraded: page.degraded(),
+        }
+    }
+
+    /// For pages that were taken from a whole file (e.g. a previous download) instead of fetched one by one.
+    pub(crate) fn without_source(index: u16, source_url: Option<Url>) -> Self {
+        Self {
+            index,
+            label: None,
+            source_url: source_url.as_ref().map(Url::to_string),
+            source_sha256: None,
+            resources: Vec::new(),
+            failure: None,
+            degraded: false,
+        }
+    }
+}
+
+/// Result of `verify`.
+#[derive(Debug, Clone, Default, Getters)]
+#[getset(get = "pub")]
+pub struct Verification {
+    problems: Vec<Problem>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Display for Verification {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_ok() {
+            return writeln!(f, "OK");
+        }
+
+        for problem in &self.problems {
+            writeln!(f, "- {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The file was changed or truncated after it was written.
+    ChecksumMismatch { expected: String, actual: String },
+    /// The file isn't a PDF that can be read.
+    Unreadable(String),
+    /// The file doesn't have as many pages as the manifest lists.
+    PageCountMismatch { expected: usize, actual: usize },
+    /// Fewer pages than the volume has were downloaded.
+    IncompleteVolume {
+        volume: String,
+        expected: u16,
+        actual: u16,
+    },
+    /// Pages that were replaced in a best-effort download.
+    ReplacedPages { volume: String, pages: Vec<u16> },
+    /// Pages that were converted from a lower resolution, see `Page::degraded`.
+    DegradedPages { volume: String, pages: Vec<u16> },
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Problem::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum is {actual}, expected {expected}")
+            }
+            Problem::Unreadable(error) => write!(f, "not a readable pdf: {error}"),
+            Problem::PageCountMismatch { expected, actual } => {
+                write!(f, "has {actual} pages, expected {expected}")
+            }
+            Problem::IncompleteVolume {
+                volume,
+                expected,
+                actual,
+            } => write!(f, "{volume}: only {actual} of {expected} pages"),
+            Problem::ReplacedPages { volume, pages } => {
+                write!(f, "{volume}: pages {pages:?} were replaced")
+            }
+            Problem::DegradedPages { volume, pages } => {
+                write!(f, "{volume}: pages {pages:?} have a lower resolution")
+            }
+        }
+    }
+}
+
+/// Checks the PDF at `pdf_path` against the manifest saved next to it.
+pub async fn verify(pdf_path: &Path) -> Result<Verification, std::io::Error> {
+    Manifest::load(pdf_path).await?.verify(pdf_path).await
+}
+
+fn hash(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+async fn hash_file(path: &Path) -> Result<String, std::io::Error> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buf).await? {
+            0 => return Ok(hex(&hasher.finalize())),
+            read => hasher.update(&buf[..read]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_size::PageSize;
+    use crate::scraper::util::placeholder_page;
+    use crate::scraper::PdfWriter;
+    use tokio::io::AsyncWrite;
+
+    fn page(index: u16) -> PageManifest {
+        PageManifest::without_source(index, None)
+    }
+
+    /// Writes a PDF with `page_count` pages to a new directory and returns its path.
+    async fn write_pdf(name: &str, page_count: u16) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "digi_download_manifest_{name}_{}",
+            std::process::id()
+        ));
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let path = dir.join("book.pdf");
+
+        let mut buf = Vec::new();
+        let out: &mut (dyn AsyncWrite + Unpin + Send) = &mut buf;
+        let mut writer = PdfWriter::new(out).await.unwrap();
+        writer.start_section(1..=page_count);
+        for _ in 0..page_count {
+            let document = placeholder_page(PageSize::A4, &[]);
+            writer.add_document(document).await.unwrap();
+        }
+        writer.finish().await.unwrap();
+
+        tokio::fs::write(&path, buf).await.unwrap();
+        path
+    }
+
+    async fn remove_dir(pdf_path: &Path) {
+        tokio::fs::remove_dir_all(pdf_path.parent().unwrap())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn verifies_complete_files() {
+        let path = write_pdf("complete", 2).await;
+        let manifest =
+            Manifest::for_file(&path, vec![VolumeManifest::new(2, vec![page(1), page(2)])])
+                .await
+                .unwrap();
+        manifest.save(&path).await.unwrap();
+
+        let manifest = Manifest::load(&path).await.unwrap();
+        assert_eq!(manifest.page_count(), 2);
+        assert!(manifest.verify(&path).await.unwrap().is_ok());
+        remove_dir(&path).await;
+    }
+
+    #[tokio::test]
+    async fn detects_changed_files() {
+        let path = write_pdf("changed", 2).await;
+        let manifest =
+            Manifest::for_file(&path, vec![VolumeManifest::new(2, vec![page(1), page(2)])])
+                .await
+                .unwrap();
+
+        let other = write_pdf("changed_other", 1).await;
+        tokio::fs::copy(&other, &path).await.unwrap();
+        let problems = manifest.verify(&path).await.unwrap().problems().clone();
+        assert_eq!(problems.len(), 2);
+        assert!(matches!(problems[0], Problem::ChecksumMismatch { .. }));
+        assert_eq!(
+            problems[1],
+            Problem::PageCountMismatch {
+                expected: 2,
+                actual: 1
+            }
+        );
+        remove_dir(&path).await;
+        remove_dir(&other).await;
+    }
+
+    #[tokio::test]
+    async fn reports_incomplete_replaced_and_degraded_pages() {
+        let path = write_pdf("incomplete", 2).await;
+        let mut replaced = page(1);
+        replaced.failure = Some(
+            serde_json::from_str(
+                r#"{"page":1,"attempts":3,"error":"timed out","replacement":"placeholder"}"#,
+            )
+            .unwrap(),
+        );
+        let mut degraded = page(2);
+        degraded.degraded = true;
+
+        let manifest = Manifest::for_file(
+            &path,
+            vec![VolumeManifest::new(3, vec![replaced, degraded])],
+        )
+        .await
+        .unwrap();
+        let problems = manifest.verify(&path).await.unwrap().problems().clone();
+        assert_eq!(
+            problems,
+            [
+                Problem::IncompleteVolume {
+                    volume: "volume 1".into(),
+                    expected: 3,
+                    actual: 2
+                },
+                Problem::ReplacedPages {
+                    volume: "volume 1".into(),
+                    pages: vec![1]
+                },
+                Problem::DegradedPages {
+                    volume: "volume 1".into(),
+                    pages: vec![2]
+                },
+            ]
+        );
+        remove_dir(&path).await;
+    }
+
+    #[test]
+    fn reads_manifests_without_degraded_pages() {
+        let page: PageManifest = serde_json::from_str(
+            r#"{"index":1,"label":null,"source_url":null,"source_sha256":null,"resources":[],"failure":null}"#,
+        )
+        .unwrap();
+        assert!(!page.degraded());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::page_size::PageSize;
+use crate::scraper::util::{fit_page, page_size};
+use getset::CopyGetters;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageEncoder};
+use lopdf::Document;
+use std::str::FromStr;
+
+/// Controls how raster images are processed before a page is converted to PDF.
+/// Page images are served at full source resolution, which makes exported books very large.
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct OutputProfile {
+    /// Re-encodes opaque images as JPEG with the given quality (1-100).
+    jpeg_quality: Option<u8>,
+    /// Downsamples images that exceed this resolution at their size on the page.
+    max_dpi: Option<u16>,
+    grayscale: bool,
+    /// Scales every page to fit this paper size, turned to the orientation of the page.
+    /// Pages keep their own size if it is `None`.
+    paper_size: Option<PageSize>,
+}
+
+impl OutputProfile {
+    /// Small files meant for reading on a screen.
+    pub const SCREEN: Self = Self::new(Some(60), Some(110), false);
+    /// Enough resolution for printing at common paper sizes.
+    pub const PRINT: Self = Self::new(Some(85), Some(300), false);
+    /// Keeps all images exactly as they are served.
+    pub const ARCHIVE: Self = Self::new(None, None, false);
+
+    /// Page units in the converted PDF are points (1/72 inch).
+    const UNITS_PER_INCH: f32 = 72.0;
+
+    pub const fn new(jpeg_quality: Option<u8>, max_dpi: Option<u16>, grayscale: bool) -> Self {
+        Self {
+            jpeg_quality,
+            max_dpi,
+            grayscale,
+            paper_size: None,
+        }
+    }
+
+    pub const fn with_grayscale(mut self, grayscale: bool) -> Self {
+        self.grayscale = grayscale;
+        self
+    }
+
+    pub const fn with_paper_size(mut self, paper_size: Option<PageSize>) -> Self {
+        self.paper_size = paper_size;
+        self
+    }
+
+    fn is_lossless(&self) -> bool {
+        self.jpeg_quality.is_none() && self.max_dpi.is_none() && !self.grayscale
+    }
+
+    /// Applies the profile to a downloaded image.
+    /// `display_size` is the size (width, height) the image is drawn at on the page, if known.
+    ///
+    /// Returns the new content type and image data.
+    /// The original image is kept if it can't be decoded or processing doesn't make it smaller.
+    pub(crate) fn process_image(
+        &self,
+        content_type: &str,
+        data: &[u8],
+        display_size: Option<(f32, f32)>,
+    ) -> (String, Vec<u8>) {
+        let original = || (content_type.to_string(), data.to_vec());
+
+        if self.is_lossless() {
+            return original();
+        }
+
+        let Ok(mut img) = image::load_from_memory(data) else {
+            return original();
+        };
+
+        if let (Some(max_dpi), Some((width, height))) = (self.max_dpi, display_size) {
+            let max_width = (width / Self::UNITS_PER_INCH * f32::from(max_dpi)).ceil() as u32;
+            let max_height = (height / Self::UNITS_PER_INCH * f32::from(max_dpi)).ceil() as u32;
+
+            if max_width > 0
+                && max_height > 0
+                && (img.width() > max_width || img.height() > max_height)
+            {
+                img = img.resize(max_width, max_height, FilterType::Lanczos3);
+            }
+        }
+
+        if self.grayscale {
+            img = match img.color().has_alpha() {
+                true => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+                false => DynamicImage::ImageLuma8(img.to_luma8()),
+            };
+        }
+
+        let encoded = match self.jpeg_quality {
+            // JPEG doesn't support transparency
+            Some(quality) if !img.color().has_alpha() => Self::encode_jpeg(&img, quality),
+            _ => Self::encode_png(&img),
+        };
+
+        match encoded {
+            Some((content_type, encoded)) if encoded.len() < data.len() => {
+                (content_type.to_string(), encoded)
+            }
+            _ => original(),
+        }
+    }
+
+    /// Scales every page of `document` to the paper size, if the profile has one.
+    pub(crate) fn apply_paper_size(&self, document: &mut Document) {
+        let Some(paper_size) = self.paper_size else {
+            return;
+        };
+
+        for page in document.get_pages().into_values() {
+            let size = page_size(document, page).unwrap_or(paper_size);
+            fit_page(
+                document,
+                page,
+                paper_size.with_orientation(size.orientation()),
+            );
+        }
+    }
+
+    fn encode_jpeg(img: &DynamicImage, quality: u8) -> Option<(&'static str, Vec<u8>)> {
+        let img = match img {
+            DynamicImage::ImageLuma8(_) => img.clone(),
+            _ => DynamicImage::ImageRgb8(img.to_rgb8()),
+        };
+
+        let mut buf = Vec::new();
+        JpegEncoder::new_with_quality(&mut buf, quality.clamp(1, 100))
+            .encode_image(&img)
+            .ok()?;
+        Some(("image/jpeg", buf))
+    }
+
+    fn encode_png(img: &DynamicImage) -> Option<(&'static str, Vec<u8>)> {
+        let img = match img {
+            DynamicImage::ImageLuma8(_)
+            | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_)
+            | DynamicImage::ImageRgba8(_) => img.clone(),
+            _ => DynamicImage::ImageRgba8(img.to_rgba8()),
+        };
+
+        let mut buf = Vec::new();
+        PngEncoder::new(&mut buf)
+            .write_image(
+                img.as_bytes(),
+                img.width(),
+                img.height(),
+                img.color().into(),
+            )
+            .ok()?;
+        Some(("image/png", buf))
+    }
+}
+
+impl Default for OutputProfile {
+    fn default() -> Self {
+        Self::ARCHIVE
+    }
+}
+
+impl FromStr for OutputProfile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "screen" => Ok(Self::SCREEN),
+            "print" => Ok(Self::PRINT),
+            "archive" => Ok(Self::ARCHIVE),
+            _ => Err(format!(
+                "Unknown output profile '{s}', expected 'screen', 'print' or 'archive'"
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage, RgbaImage};
+    use std::io::Cursor;
+
+    /// Noise compresses badly, so every processed image is smaller than the original.
+    fn noise(width: u32, height: u32) -> RgbImage {
+        let mut seed = 1u32;
+        RgbImage::from_fn(width, height, |_, _| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            let [r, g, b, _] = seed.to_le_bytes();
+            image::Rgb([r, g, b])
+        })
+    }
+
+    fn encode(img: impl Into<DynamicImage>, format: ImageFormat) -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        img.into().write_to(&mut buf, format).unwrap();
+        buf.into_inner()
+    }
+
+    #[test]
+    fn downsamples_to_max_dpi() {
+        let png = encode(noise(400, 200), ImageFormat::Png);
+        let profile = OutputProfile::new(None, Some(72), false);
+
+        // 100pt at 72 dpi are 100 pixels
+        let (content_type, data) = profile.process_image("image/png", &png, Some((100.0, 50.0)));
+        assert_eq!(content_type, "image/png");
+        let img = image::load_from_memory(&data).unwrap();
+        assert_eq!((img.width(), img.height()), (100, 50));
+
+        // without a display size the resolution is unknown
+        let (_, data) = profile.process_image("image/png", &png, None);
+        assert_eq!(data, png);
+    }
+
+    #[test]
+    fn converts_to_grayscale() {
+        let png = encode(noise(64, 64), ImageFormat::Png);
+        let profile = OutputProfile::ARCHIVE.with_grayscale(true);
+
+        let (content_type, data) = profile.process_image("image/png", &png, None);
+        assert_eq!(content_type, "image/png");
+        let img = image::load_from_memory(&data).unwrap();
+        assert_eq!(img.color(), image::ColorType::L8);
+    }
+
+    #[test]
+    fn reencodes_as_jpeg_with_profile_quality() {
+        let img = noise(64, 64);
+        let png = encode(img.clone(), ImageFormat::Png);
+
+        let (content_type, data) =
+            OutputProfile::new(Some(50), None, false).process_image("image/png", &png, None);
+        assert_eq!(content_type, "image/jpeg");
+
+        let (_, expected) = OutputProfile::encode_jpeg(&img.into(), 50).unwrap();
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn keeps_original_if_processing_is_not_smaller() {
+        let jpeg = encode(RgbImage::new(8, 8), ImageFormat::Jpeg);
+
+        let (content_type, data) =
+            OutputProfile::new(Some(100), None, false).process_image("image/jpeg", &jpeg, None);
+        assert_eq!(content_type, "image/jpeg");
+        assert_eq!(data, jpeg);
+    }
+
+    #[test]
+    fn keeps_transparent_images_as_png() {
+        let rgb = noise(64, 64);
+        let rgba = RgbaImage::from_fn(64, 64, |x, y| {
+            let [r, g, b] = rgb.get_pixel(x, y).0;
+            image::Rgba([r, g, b, (x * 4) as u8])
+        });
+        let png = encode(rgba, ImageFormat::Png);
+
+        let (content_type, data) = OutputProfile::new(Some(50), Some(72), false).process_image(
+            "image/png",
+            &png,
+            Some((32.0, 32.0)),
+        );
+        assert_eq!(content_type, "image/png");
+        assert!(image::load_from_memory(&data).unwrap().color().has_alpha());
+    }
+}
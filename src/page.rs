@@ -0,0 +1,136 @@
+use crate::page_size::PageSize;
+use crate::scraper::util;
+use bytes::Bytes;
+use getset::{CopyGetters, Getters};
+use lopdf::Document;
+use reqwest::Url;
+use serde::Deserialize;
+
+/// A single page of a volume, see `Scraper::fetch_page`.
+/// Holds the page as it was served next to its conversion,
+/// so it can be inspected or post-processed before it is written.
+#[derive(Debug, Clone, Getters, CopyGetters)]
+pub struct Page {
+    /// One-based number of the page within the volume.
+    #[getset(get_copy = "pub")]
+    index: u16,
+    /// Page number as printed in the book (e.g. `iv` or `12`), if the viewer specifies it.
+    #[getset(get = "pub")]
+    label: Option<String>,
+    /// Size of the converted page.
+    #[getset(get_copy = "pub")]
+    size: PageSize,
+    /// Where the page itself is served from, if it is a file of its own.
+    #[getset(get = "pub")]
+    source_url: Option<Url>,
+    /// Files referenced by the page (e.g. images of an svg), in the order they are first referenced.
+    #[getset(get = "pub")]
+    resources: Vec<PageResource>,
+    /// Only for pages served as svgs.
+    #[getset(get = "pub")]
+    raw_svg: Option<String>,
+    /// Set if the page was converted from a lower resolution than usual (e.g. a thumbnail),
+    /// because the original isn't available.
+    #[getset(get_copy = "pub")]
+    degraded: bool,
+    #[getset(get = "pub")]
+    pdf: Document,
+}
+
+impl Page {
+    /// The size is read from the `MediaBox` of `pdf`.
+    pub(crate) fn new(index: u16, label: Option<String>, pdf: Document) -> Self {
+        let size = pdf
+            .get_pages()
+            .values()
+            .next()
+            .and_then(|page| util::page_size(&pdf, *page))
+            .unwrap_or(PageSize::A4);
+
+        Self {
+            index,
+            label,
+            size,
+            source_url: None,
+            resources: Vec::new(),
+            raw_svg: None,
+            degraded: false,
+            pdf,
+        }
+    }
+
+    pub(crate) fn with_source_url(self, source_url: Option<Url>) -> Self {
+        Self { source_url, ..self }
+    }
+
+    pub(crate) fn with_resources(self, resources: Vec<PageResource>) -> Self {
+        Self { resources, ..self }
+    }
+
+    pub(crate) fn with_raw_svg(self, raw_svg: String) -> Self {
+        Self {
+            raw_svg: Some(raw_svg),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_degraded(self, degraded: bool) -> Self {
+        Self { degraded, ..self }
+    }
+
+    /// The label, or the index if there is none.
+    pub fn display_label(&self) -> String {
+        match &self.label {
+            Some(label) => label.clone(),
+            None => self.index.to_string(),
+        }
+    }
+
+    pub fn into_pdf(self) -> Document {
+        self.pdf
+    }
+}
+
+/// A file referenced by a page, exactly as it was served.
+#[derive(Debug, Clone, Getters)]
+#[getset(get = "pub")]
+pub struct PageResource {
+    url: Url,
+    content_type: Option<String>,
+    /// Shares the buffer of the response.
+    data: Bytes,
+}
+
+impl PageResource {
+    pub(crate) fn new(url: Url, content_type: Option<String>, data: Bytes) -> Self {
+        Self {
+            url,
+            content_type,
+            data,
+        }
+    }
+}
+
+/// An entry of the table of contents of a volume, see `BaseScraper::get_chapters`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Getters, CopyGetters)]
+pub struct Chapter {
+    #[getset(get = "pub")]
+    title: String,
+    /// One-based number of the page the chapter starts on.
+    #[getset(get_copy = "pub")]
+    page: u16,
+    /// Sections of the chapter, in reading order.
+    #[serde(default)]
+    #[getset(get = "pub")]
+    children: Vec<Chapter>,
+}
+
+impl Chapter {
+    pub fn new(title: impl Into<String>, page: u16, children: Vec<Chapter>) -> Self {
+        Self {
+            title: title.into(),
+            page,
+            children,
+        }
+    }
+}
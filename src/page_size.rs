@@ -0,0 +1,212 @@
+use crate::regex;
+use getset::CopyGetters;
+use std::fmt::Display;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+/// Physical size of a page in points (1/72 inch).
+#[derive(Debug, Clone, Copy, PartialEq, CopyGetters)]
+#[getset(get_copy = "pub")]
+pub struct PageSize {
+    width: f32,
+    height: f32,
+}
+
+impl PageSize {
+    pub const A3: Self = Self::from_mm(297.0, 420.0);
+    pub const A4: Self = Self::from_mm(210.0, 297.0);
+    pub const A5: Self = Self::from_mm(148.0, 210.0);
+    pub const LETTER: Self = Self::new(612.0, 792.0);
+    pub const LEGAL: Self = Self::new(612.0, 1008.0);
+
+    const POINTS_PER_MM: f32 = 72.0 / 25.4;
+
+    pub const fn new(width: f32, height: f32) -> Self {
+        Self { width, height }
+    }
+
+    pub const fn from_mm(width: f32, height: f32) -> Self {
+        Self::new(width * Self::POINTS_PER_MM, height * Self::POINTS_PER_MM)
+    }
+
+    /// Square pages count as portrait.
+    pub fn orientation(&self) -> Orientation {
+        match self.width > self.height {
+            true => Orientation::Landscape,
+            false => Orientation::Portrait,
+        }
+    }
+
+    /// The same size turned to `orientation`.
+    pub fn with_orientation(self, orientation: Orientation) -> Self {
+        match self.orientation() == orientation {
+            true => self,
+            false => Self::new(self.height, self.width),
+        }
+    }
+
+    /// Reads the size of an svg from the `width` and `height` of its root element,
+    /// or from its `viewBox` if those are missing or relative.
+    /// User units (and `px`) are treated as points, like the svg conversion does.
+    pub(crate) fn from_svg(svg: &str) -> Option<Self> {
+        let tag = regex!(r"<svg\s[^>]*>").find(svg)?.as_str();
+        let attribute = |name: &str| svg_attribute(tag, name).map(str::trim);
+
+        let view_box = attribute("viewBox").and_then(|view_box| {
+            let values: Vec<f32> = view_box
+                .split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse().ok())
+                .collect::<Option<_>>()?;
+
+            match values[..] {
+                [_, _, width, height] if width > 0.0 && height > 0.0 => Some((width, height)),
+                _ => None,
+            }
+        });
+
+        let width = attribute("width").and_then(parse_length);
+        let height = attribute("height").and_then(parse_length);
+
+        match (width, height, view_box) {
+            (Some(width), Some(height), _) => Some(Self::new(width, height)),
+            // a single given length keeps the aspect ratio of the view box
+            (Some(width), None, Some((vb_width, vb_height))) => {
+                Some(Self::new(width, width * vb_height / vb_width))
+            }
+            (None, Some(height), Some((vb_width, vb_height))) => {
+                Some(Self::new(height * vb_width / vb_height, height))
+            }
+            (_, _, Some((width, height))) => Some(Self::new(width, height)),
+            _ => None,
+        }
+    }
+
+    /// Whether both sizes differ by less than half a point in each dimension.
+    pub(crate) fn approx_eq(&self, other: &Self) -> bool {
+        (self.width - other.width).abs() < 0.5 && (self.height - other.height).abs() < 0.5
+    }
+}
+
+impl Display for PageSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.0} x {:.0} mm",
+            self.width / Self::POINTS_PER_MM,
+            self.height / Self::POINTS_PER_MM
+        )
+    }
+}
+
+impl FromStr for PageSize {
+    type Err = String;
+
+    /// Either a paper size name or `{width}x{height}` with units (`210mmx297mm`, `8.5inx11in`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "a3" => Ok(Self::A3),
+            "a4" => Ok(Self::A4),
+            "a5" => Ok(Self::A5),
+            "letter" => Ok(Self::LETTER),
+            "legal" => Ok(Self::LEGAL),
+            // the separator can't simply be split at, `px` contains it as well
+            size => regex!(r"^\s*([\d.]+\s*[a-z]*?)\s*x\s*([\d.]+\s*[a-z]*)\s*$")
+                .captures(size)
+                .and_then(|c| Some(Self::new(parse_length(&c[1])?, parse_length(&c[2])?)))
+                .filter(|size| size.width > 0.0 && size.height > 0.0)
+                .ok_or_else(|| {
+                    format!(
+                        "Unknown paper size '{s}', expected e.g. 'a4', 'letter' or '210mmx297mm'"
+                    )
+                }),
+        }
+    }
+}
+
+fn svg_attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    regex!(r#"\s([\w:-]+)\s*=\s*"([^"]*)""#)
+        .captures_iter(tag)
+        .find(|c| &c[1] == name)
+        .map(|c| c.get(2).unwrap().as_str())
+}
+
+/// Converts an absolute length to points. Percentages can't be resolved without a viewport.
+fn parse_length(length: &str) -> Option<f32> {
+    let c = regex!(r"^([\d.]+)\s*(pt|px|mm|cm|in|pc)?$").captures(length)?;
+    let value: f32 = c[1].parse().ok()?;
+
+    Some(match c.get(2).map(|unit| unit.as_str()) {
+        None | Some("pt") | Some("px") => value,
+        Some("mm") => value * PageSize::POINTS_PER_MM,
+        Some("cm") => value * 10.0 * PageSize::POINTS_PER_MM,
+        Some("in") => value * 72.0,
+        Some("pc") => value * 12.0,
+        Some(_) => unreachable!(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_size(size: PageSize, width: f32, height: f32) {
+        assert!(
+            size.approx_eq(&PageSize::new(width, height)),
+            "{size:?} is not {width} x {height}"
+        );
+    }
+
+    #[test]
+    fn parses_paper_names() {
+        assert_eq!("A4".parse(), Ok(PageSize::A4));
+        assert_eq!("letter".parse(), Ok(PageSize::LETTER));
+    }
+
+    #[test]
+    fn parses_lengths_with_units() {
+        assert_size("210mmx297mm".parse().unwrap(), 595.3, 841.9);
+        assert_size("8.5in x 11in".parse().unwrap(), 612.0, 792.0);
+        assert_size("595pxx842px".parse().unwrap(), 595.0, 842.0);
+        assert_size("595x842".parse().unwrap(), 595.0, 842.0);
+        assert_size("21cmx29.7cm".parse().unwrap(), 595.3, 841.9);
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        for size in ["", "a6", "210mm", "0x297mm", "210ftx297ft", "x297mm"] {
+            assert!(size.parse::<PageSize>().is_err(), "{size}");
+        }
+    }
+
+    #[test]
+    fn reads_svg_size() {
+        assert_size(
+            PageSize::from_svg(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" width="210mm" height="297mm">"#,
+            )
+            .unwrap(),
+            595.3,
+            841.9,
+        );
+        // relative lengths fall back to the view box
+        assert_size(
+            PageSize::from_svg(r#"<svg width="100%" height="100%" viewBox="0 0 595 842">"#)
+                .unwrap(),
+            595.0,
+            842.0,
+        );
+        // a single length keeps the aspect ratio
+        assert_size(
+            PageSize::from_svg(r#"<svg width="300" viewBox="0,0,600,800">"#).unwrap(),
+            300.0,
+            400.0,
+        );
+        assert_eq!(PageSize::from_svg(r#"<svg width="100%">"#), None);
+    }
+}
@@ -1,14 +1,46 @@
 use crate::buffered_response::BufferedResponse;
+use crate::error::ScraperError;
+use crate::http::HttpClient;
+use crate::page::Chapter;
 use crate::scraper::scraper_trait::Scraper;
 use async_trait::async_trait;
-use reqwest::Client;
+use reqwest::Url;
 use std::sync::Arc;
 
 #[async_trait]
 pub trait BaseScraper {
-    fn new_scraper(resp: Arc<BufferedResponse>, client: Arc<Client>) -> Box<dyn Scraper>
+    /// Fails if the first response of the volume lacks something the scraper needs.
+    fn new_scraper(
+        resp: Arc<BufferedResponse>,
+        client: Arc<HttpClient>,
+    ) -> Result<Box<dyn Scraper>, ScraperError>
     where
         Self: Sized;
 
-    async fn fetch_page_count(&self) -> Result<u16, reqwest::Error>;
+    /// Recognizes the viewer of this scraper in the first response of a volume,
+    /// independent of the host it is served from.
+    /// Only used if the scraper is registered with `ScraperRegistry::register_probe`.
+    fn probe(_resp: &BufferedResponse) -> bool
+    where
+        Self: Sized,
+    {
+        false
+    }
+
+    async fn fetch_page_count(&self) -> Result<u16, ScraperError>;
+
+    /// Page number as printed in the book (e.g. `iv`), if the viewer specifies it.
+    fn get_page_label(&self, _page: u16) -> Option<String> {
+        None
+    }
+
+    /// Table of contents of the volume, empty if the viewer doesn't specify one.
+    fn get_chapters(&self) -> Vec<Chapter> {
+        Vec::new()
+    }
+
+    /// Where the (one-based) page is served from, if it is a file of its own.
+    fn get_page_url(&self, _page: u16) -> Option<Url> {
+        None
+    }
 }
@@ -0,0 +1,250 @@
+use crate::error::ScraperError;
+use crate::page_size::PageSize;
+use crate::regex;
+use crate::scraper::util::fit_page;
+use lopdf::{Dictionary, Document, Object, StringFormat};
+use std::io::Cursor;
+
+/// Prefix of the ids given to the groups replacing `<a>` elements.
+const LINK_ID_PREFIX: &str = "digi_download_link_";
+
+/// Prefix of the named destinations internal links point to.
+/// The number of the page (within the book) follows it, `PdfWriter` resolves them.
+pub(crate) const PAGE_DESTINATION_PREFIX: &str = "digi_download_page_";
+
+#[derive(Debug, PartialEq, Eq)]
+enum LinkTarget {
+    Uri(String),
+    /// One-based page number within the book.
+    Page(u16),
+}
+
+impl LinkTarget {
+    fn parse(href: &str) -> Option<Self> {
+        if let Some(c) = regex!(r"(?i)(?:#page=|goToPage\(\s*)(\d+)").captures(href) {
+            return c[1].parse().ok().map(Self::Page);
+        }
+
+        match href.split_once(':') {
+            Some((scheme, _))
+                if ["http", "https", "mailto"].contains(&scheme.to_lowercase().as_str()) =>
+            {
+                Some(Self::Uri(href.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Converts a page and keeps its `<a>` elements as clickable link annotations.
+///
+/// usvg drops links, so every `<a>` is replaced by a group with a known id
+/// whose bounding box becomes the clickable area.
+///
+/// usvg resolves absolute units (`mm`, `in`, ...) at 96 dpi while the conversion uses points,
+/// so such pages are scaled to their physical size afterwards.
+pub(crate) fn svg_to_pdf_with_links(svg: &str) -> Result<Vec<u8>, ScraperError> {
+    let (svg, targets) = replace_links(svg);
+
+    let tree = svg2pdf::usvg::Tree::from_str(&svg, &Default::default())?;
+    let pdf = svg2pdf::to_pdf(&tree, Default::default(), Default::default())
+        .map_err(ScraperError::SvgConversion)?;
+
+    let height = tree.size().height();
+    let annotations: Vec<Object> = targets
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, target)| {
+            // links without content (e.g. invisible shapes) are removed by usvg
+            let bounds = tree
+                .node_by_id(&format!("{LINK_ID_PREFIX}{i}"))?
+                .abs_bounding_box();
+
+            Some(Object::Dictionary(link_annotation(
+                target?,
+                [
+                    bounds.left(),
+                    height - bounds.bottom(),
+                    bounds.right(),
+                    height - bounds.top(),
+                ],
+            )))
+        })
+        .collect();
+
+    let size = PageSize::from_svg(&svg)
+        .filter(|size| !size.approx_eq(&PageSize::new(tree.size().width(), height)));
+
+    if annotations.is_empty() && size.is_none() {
+        return Ok(pdf);
+    }
+
+    let mut document = Document::load_from(Cursor::new(pdf))?;
+    let page = *document
+        .get_pages()
+        .values()
+        .next()
+        .ok_or(lopdf::Error::PageNumberNotFound(1))?;
+    if !annotations.is_empty() {
+        document
+            .get_dictionary_mut(page)?
+            .set("Annots", annotations);
+    }
+    if let Some(size) = size {
+        fit_page(&mut document, page, size);
+    }
+
+    let mut buf = Vec::new();
+    document.save_to(&mut buf)?;
+    Ok(buf)
+}
+
+/// Replaces every `<a>` element by a group with the same attributes, apart from its link and id.
+/// Returns the target of every link, in the order of the ids.
+fn replace_links(svg: &str) -> (String, Vec<Option<LinkTarget>>) {
+    let mut targets = Vec::new();
+
+    // attribute values may contain `>`
+    let svg = regex!(r#"<a((?:\s(?:[^>"']|"[^"]*"|'[^']*')*)?)>|</a\s*>"#).replace_all(
+        svg,
+        |c: &regex::Captures| {
+            if c[0].starts_with("</") {
+                return "</g>".to_string();
+            }
+
+            let attributes = c.get(1).map_or("", |m| m.as_str());
+            let (attributes, end) = match attributes.trim_end().strip_suffix('/') {
+                Some(attributes) => (attributes, "/>"),
+                None => (attributes, ">"),
+            };
+
+            let mut href = None;
+            let mut kept = String::new();
+            for attribute in
+                regex!(r#"([^\s=/]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).captures_iter(attributes)
+            {
+                let value = attribute
+                    .get(2)
+                    .or(attribute.get(3))
+                    .map_or("", |m| m.as_str());
+                match &attribute[1] {
+                    "href" | "xlink:href" => href = Some(value.replace("&amp;", "&")),
+                    // the group needs an id of its own
+                    "id" => {}
+                    _ => {
+                        kept.push(' ');
+                        kept.push_str(&attribute[0]);
+                    }
+                }
+            }
+
+            targets.push(href.as_deref().and_then(LinkTarget::parse));
+            format!(
+                r#"<g id="{LINK_ID_PREFIX}{}"{kept}{end}"#,
+                targets.len() - 1
+            )
+        },
+    );
+
+    (svg.into_owned(), targets)
+}
+
+fn link_annotation(target: LinkTarget, rect: [f32; 4]) -> Dictionary {
+    let mut annotation = Dictionary::new();
+    annotation.set("Type", "Annot");
+    annotation.set("Subtype", "Link");
+    annotation.set("Rect", rect.map(Object::Real).to_vec());
+    // no visible border
+    annotation.set("Border", vec![0.into(), 0.into(), 0.into()]);
+
+    match target {
+        LinkTarget::Uri(uri) => {
+            let mut action = Dictionary::new();
+            action.set("S", "URI");
+            action.set(
+                "URI",
+                Object::String(uri.into_bytes(), StringFormat::Literal),
+            );
+            annotation.set("A", action);
+        }
+        LinkTarget::Page(page) => annotation.set(
+            "Dest",
+            Object::String(
+                format!("{PAGE_DESTINATION_PREFIX}{page}").into_bytes(),
+                StringFormat::Literal,
+            ),
+        ),
+    }
+
+    annotation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_link_targets() {
+        assert_eq!(
+            LinkTarget::parse("javascript:IDRViewer.goToPage( 12 )"),
+            Some(LinkTarget::Page(12))
+        );
+        assert_eq!(LinkTarget::parse("#page=3"), Some(LinkTarget::Page(3)));
+        assert_eq!(
+            LinkTarget::parse("HTTPS://example.com/a?b=c"),
+            Some(LinkTarget::Uri("HTTPS://example.com/a?b=c".into()))
+        );
+        assert_eq!(LinkTarget::parse("javascript:void(0)"), None);
+    }
+
+    #[test]
+    fn keeps_attributes_of_links() {
+        let (svg, targets) = replace_links(
+            r#"<a id="x" xlink:href="https://example.com/?a=1&amp;b=2" transform="translate(5 0)" style='fill:red'><rect/></a>"#,
+        );
+
+        assert_eq!(
+            svg,
+            r#"<g id="digi_download_link_0" transform="translate(5 0)" style='fill:red'><rect/></g>"#
+        );
+        assert_eq!(
+            targets,
+            [Some(LinkTarget::Uri("https://example.com/?a=1&b=2".into()))]
+        );
+    }
+
+    #[test]
+    fn only_replaces_a_elements() {
+        let svg =
+            r##"<abbr/><animate attributeName="x"/><a href="#page=2" title="a > b"/><a>text</a >"##;
+        let (svg, targets) = replace_links(svg);
+
+        assert_eq!(
+            svg,
+            r##"<abbr/><animate attributeName="x"/><g id="digi_download_link_0" title="a > b"/><g id="digi_download_link_1">text</g>"##
+        );
+        assert_eq!(targets, [Some(LinkTarget::Page(2)), None]);
+    }
+
+    #[test]
+    fn link_area_follows_transform() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100"><a href="https://example.com" transform="translate(50 0)"><rect width="10" height="10"/></a></svg>"#;
+        let document = Document::load_mem(&svg_to_pdf_with_links(svg).unwrap()).unwrap();
+
+        let page = *document.get_pages().values().next().unwrap();
+        let annots = document
+            .get_dictionary(page)
+            .unwrap()
+            .get(b"Annots")
+            .and_then(Object::as_array)
+            .unwrap();
+        let rect = annots[0]
+            .as_dict()
+            .unwrap()
+            .get(b"Rect")
+            .and_then(Object::as_array)
+            .unwrap();
+        let left = rect[0].as_float().unwrap();
+        assert!((left - 50.0).abs() < 0.5, "{rect:?}");
+    }
+}
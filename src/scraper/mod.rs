@@ -1,9 +1,18 @@
-pub use scraper_structs::get_scraper_constructor;
-
 mod base_scraper;
+mod links;
+pub(crate) mod outline;
+pub(crate) mod pdf_writer;
+mod raster_scraper;
+mod registry;
 mod scraper_structs;
 pub(crate) mod scraper_trait;
 mod svg_scraper;
-mod util;
+pub(crate) mod util;
 
+pub use base_scraper::BaseScraper;
+pub use pdf_writer::PdfWriter;
+pub use raster_scraper::RasterScraper;
+pub use registry::{ScraperConstructor, ScraperRegistry};
+pub use scraper_trait::Scraper;
+pub use svg_scraper::SvgScraper;
 pub use util::merge_pdf;
@@ -0,0 +1,151 @@
+use crate::page::Chapter;
+use crate::scraper::pdf_writer::PdfWriter;
+use tokio::io::AsyncWrite;
+
+/// Adds the bookmarks of a book while its pages are written:
+/// a bookmark with the title of the book, the chapters below it
+/// and a bookmark for every page below the chapter it belongs to.
+pub(crate) struct BookOutline {
+    title: Option<String>,
+    root: Option<u32>,
+    /// Every chapter in reading order, with the position of its parent chapter.
+    chapters: Vec<(String, u16, Option<usize>)>,
+    /// Bookmark of every chapter whose first page was written.
+    bookmarks: Vec<Option<u32>>,
+    /// Position of the chapter the last written page belongs to.
+    current: Option<usize>,
+}
+
+impl BookOutline {
+    /// Without a `title`, the chapters are top-level bookmarks.
+    pub(crate) fn new(title: Option<String>, chapters: &[Chapter]) -> Self {
+        let mut flattened = Vec::new();
+        flatten(chapters, None, &mut flattened);
+
+        Self {
+            title,
+            root: None,
+            bookmarks: vec![None; flattened.len()],
+            chapters: flattened,
+            current: None,
+        }
+    }
+
+    /// Adds the bookmarks of the (one-based) `page`, which was written at the (zero-based) `index`.
+    /// Chapters that start on pages which aren't written are left out.
+    pub(crate) fn add_page<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: &mut PdfWriter<W>,
+        page: u16,
+        index: u32,
+    ) {
+        if let Some(title) = self.title.take() {
+            self.root = Some(writer.add_bookmark(title, index, None));
+        }
+
+        for (i, (title, _, parent)) in self.chapters.iter().enumerate() {
+            if self.chapters[i].1 != page {
+                continue;
+            }
+
+            let parent = parent
+                .and_then(|parent| self.bookmarks[parent])
+                .or(self.root);
+            self.bookmarks[i] = Some(writer.add_bookmark(title.clone(), index, parent));
+            self.current = Some(i);
+        }
+
+        let parent = self
+            .current
+            .and_then(|current| self.bookmarks[current])
+            .or(self.root);
+        writer.add_bookmark(format!("Page_{page}"), index, parent);
+    }
+}
+
+/// Lists the chapters depth first, so parents come before their children.
+fn flatten(
+    chapters: &[Chapter],
+    parent: Option<usize>,
+    out: &mut Vec<(String, u16, Option<usize>)>,
+) {
+    for chapter in chapters {
+        out.push((chapter.title().clone(), chapter.page(), parent));
+        flatten(chapter.children(), Some(out.len() - 1), out);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_size::PageSize;
+    use crate::scraper::util::placeholder_page;
+    use lopdf::Document;
+
+    /// Writes `page_count` pages with their bookmarks and returns the (level, title, page) of every bookmark.
+    async fn write_outline(
+        title: Option<String>,
+        chapters: &[Chapter],
+        page_count: u16,
+    ) -> Vec<(usize, String, usize)> {
+        let mut writer = PdfWriter::new(Vec::new()).await.unwrap();
+        let mut outline = BookOutline::new(title, chapters);
+        for page in 1..=page_count {
+            let index = writer
+                .add_document(placeholder_page(PageSize::A4, &[]))
+                .await
+                .unwrap();
+            outline.add_page(&mut writer, page, index);
+        }
+
+        let buf = writer.finish().await.unwrap();
+        Document::load_mem(&buf)
+            .unwrap()
+            .get_toc()
+            .unwrap()
+            .toc
+            .into_iter()
+            .map(|entry| (entry.level, entry.title, entry.page))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn nests_chapters_below_the_title() {
+        let chapters = [
+            Chapter::new("Algebra", 1, vec![Chapter::new("Equations", 2, Vec::new())]),
+            Chapter::new("Geometry", 3, Vec::new()),
+        ];
+
+        let toc = write_outline(Some("Maths".into()), &chapters, 3).await;
+        let toc: Vec<_> = toc
+            .iter()
+            .map(|(level, title, page)| (*level, title.as_str(), *page))
+            .collect();
+        assert_eq!(
+            toc,
+            [
+                (1, "Maths", 1),
+                (2, "Algebra", 1),
+                (3, "Page_1", 1),
+                (3, "Equations", 2),
+                (4, "Page_2", 2),
+                (2, "Geometry", 3),
+                (3, "Page_3", 3),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn without_chapters_or_title_pages_are_top_level() {
+        let toc = write_outline(None, &[], 2).await;
+        let levels: Vec<_> = toc.iter().map(|(level, _, _)| *level).collect();
+        assert_eq!(levels, [1, 1]);
+    }
+
+    #[tokio::test]
+    async fn skips_chapters_of_pages_that_are_not_written() {
+        let chapters = [Chapter::new("Appendix", 5, Vec::new())];
+        let toc = write_outline(None, &chapters, 2).await;
+        assert!(toc.iter().all(|(_, title, _)| title.starts_with("Page_")));
+    }
+}
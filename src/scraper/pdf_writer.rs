@@ -0,0 +1,746 @@
+use crate::scraper::links::PAGE_DESTINATION_PREFIX;
+use crate::scraper::util::{
+    dedup_objects, inherited_page_attributes, is_shareable, reachable_objects, replace_references,
+};
+use lopdf::{text_string, Bookmark, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::ops::RangeInclusive;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+/// Writes a PDF page by page instead of building the whole document in memory.
+/// Every page is written as soon as it is added, only the object offsets and the
+/// outline are kept until `finish` writes the page tree, outline and cross-reference table.
+///
+/// Images and fonts that were already written for a previous page are shared instead of written again.
+pub struct PdfWriter<W: AsyncWrite + Unpin> {
+    out: W,
+    /// Number of bytes written so far. Needed for the cross-reference table.
+    position: u64,
+
+    next_id: u32,
+    offsets: BTreeMap<u32, u64>,
+    pages: Vec<ObjectId>,
+    /// Hashes of every written resource that can be shared between pages.
+    shared: HashMap<[u8; 32], ObjectId>,
+
+    // only used to keep track of the bookmarks until the outline is built
+    outline: Document,
+    /// Name and file specification of every embedded file.
+    attachments: Vec<(String, ObjectId)>,
+    /// Index of the first written page of the current book and which pages of the book are written.
+    /// Internal links are resolved relative to it.
+    section: (u32, RangeInclusive<u16>),
+    /// (Zero-based) indices of all pages internal links jump to.
+    destinations: BTreeSet<u32>,
+}
+
+impl<W: AsyncWrite + Unpin> PdfWriter<W> {
+    const CATALOG_ID: ObjectId = (1, 0);
+    const PAGES_ID: ObjectId = (2, 0);
+
+    /// Writes the PDF header.
+    pub async fn new(out: W) -> std::io::Result<Self> {
+        let mut writer = Self {
+            out,
+            position: 0,
+
+            next_id: Self::PAGES_ID.0 + 1,
+            offsets: BTreeMap::new(),
+            pages: Vec::new(),
+            shared: HashMap::new(),
+
+            outline: Document::new(),
+            attachments: Vec::new(),
+            section: (0, 1..=u16::MAX),
+            destinations: BTreeSet::new(),
+        };
+
+        // the binary mark tells tools that the file contains binary data
+        writer.write(b"%PDF-1.7\n%\xE2\xE3\xCF\xD3\n").await?;
+        Ok(writer)
+    }
+
+    /// Number of pages written so far.
+    pub fn page_count(&self) -> u32 {
+        self.pages.len() as u32
+    }
+
+    /// Starts a new book (e.g. the next volume) of which the (one-based) `pages` follow.
+    /// Internal links of those pages jump to pages within the book, links to pages that
+    /// aren't part of `pages` are left without destination.
+    pub fn start_section(&mut self, pages: RangeInclusive<u16>) {
+        self.section = (self.page_count(), pages);
+    }
+
+    /// Appends all pages of `document`.
+    /// Returns the (zero-based) index of the first added page.
+    pub async fn add_document(&mut self, mut document: Document) -> std::io::Result<u32> {
+        let first_page = self.page_count();
+
+        document.compress();
+        dedup_objects(&mut document);
+        document.renumber_objects_with(self.next_id);
+        self.next_id = document.max_id + 1;
+
+        let pages: Vec<ObjectId> = document.get_pages().into_values().collect();
+        // the pages are moved to another page tree
+        for page in &pages {
+            for (key, value) in inherited_page_attributes(&document, *page) {
+                document.get_dictionary_mut(*page).unwrap().set(key, value);
+            }
+        }
+        self.share_resources(&mut document, &pages);
+        for object in document.objects.values_mut() {
+            self.resolve_destinations(object);
+        }
+
+        for object_id in reachable_objects(&document, &pages) {
+            let mut object = document.objects.remove(&object_id).unwrap();
+
+            if pages.contains(&object_id) {
+                if let Object::Dictionary(ref mut dict) = object {
+                    dict.set("Parent", Self::PAGES_ID);
+                }
+            }
+
+            let buf = serialize(&object);
+            if is_shareable(&object) {
+                self.shared.insert(Sha256::digest(&buf).into(), object_id);
+            }
+
+            self.write_object(object_id, &buf).await?;
+        }
+
+        self.pages.extend(pages);
+        Ok(first_page)
+    }
+
+    /// Adds an entry to the outline which jumps to the (zero-based) `page`.
+    /// Returns the id of the bookmark, which can be used as a `parent` for nested entries.
+    ///
+    /// # Panics
+    /// If `page` hasn't been written yet.
+    pub fn add_bookmark(&mut self, title: String, page: u32, parent: Option<u32>) -> u32 {
+        let page = *self
+            .pages
+            .get(page as usize)
+            .unwrap_or_else(|| panic!("tried bookmarking page {page} before writing it"));
+
+        self.outline
+            .add_bookmark(Bookmark::new(title, [0.0, 0.0, 1.0], 0, page), parent)
+    }
+
+    /// Embeds a file, which PDF viewers list as an attachment of the document.
+    pub async fn add_attachment(
+        &mut self,
+        name: &str,
+        mime_type: &str,
+        data: impl Into<Vec<u8>>,
+    ) -> std::io::Result<()> {
+        let data = data.into();
+        let mut params = Dictionary::new();
+        params.set("Size", data.len() as i64);
+
+        let mut file = Dictionary::new();
+        file.set("Type", "EmbeddedFile");
+        file.set("Subtype", Object::Name(mime_type.as_bytes().to_vec()));
+        file.set("Params", params);
+
+        let mut file = Stream::new(file, data);
+        // already compressed formats (audio, video, ...) don't get any smaller, which `compress` detects
+        let _ = file.compress();
+
+        let file_id = self.new_object_id();
+        self.write_object(file_id, &serialize(&Object::Stream(file)))
+            .await?;
+
+        let mut embedded_files = Dictionary::new();
+        embedded_files.set("F", file_id);
+
+        let mut file_spec = Dictionary::new();
+        file_spec.set("Type", "Filespec");
+        file_spec.set("F", text_string(name));
+        file_spec.set("UF", text_string(name));
+        file_spec.set("EF", embedded_files);
+
+        let file_spec_id = self.new_object_id();
+        self.write_object(file_spec_id, &serialize(&Object::Dictionary(file_spec)))
+            .await?;
+
+        self.attachments.push((name.to_string(), file_spec_id));
+        Ok(())
+    }
+
+    /// Writes the page tree, outline and cross-reference table.
+    /// Returns the underlying writer.
+    pub async fn finish(mut self) -> std::io::Result<W> {
+        let mut catalog = Dictionary::new();
+        catalog.set("Type", "Catalog");
+        catalog.set("Pages", Self::PAGES_ID);
+
+        self.outline.max_id = self.next_id - 1;
+        if let Some(outline_id) = self.outline.build_outline() {
+            catalog.set("Outlines", outline_id);
+            catalog.set("PageMode", "UseOutlines");
+
+            self.next_id = self.outline.max_id + 1;
+            for (object_id, object) in std::mem::take(&mut self.outline.objects) {
+                self.write_object(object_id, &serialize(&object)).await?;
+            }
+        }
+
+        let mut names = Dictionary::new();
+
+        if !self.attachments.is_empty() {
+            // the names of a name tree have to be sorted
+            self.attachments.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            let mut embedded_files = Dictionary::new();
+            embedded_files.set(
+                "Names",
+                self.attachments
+                    .iter()
+                    .flat_map(|(name, id)| [text_string(name), Object::Reference(*id)])
+                    .collect::<Vec<_>>(),
+            );
+            names.set("EmbeddedFiles", embedded_files);
+        }
+
+        // links to pages after the last one are left dangling
+        let mut destinations: Vec<(String, ObjectId)> = self
+            .destinations
+            .iter()
+            .filter_map(|&page| Some((destination_name(page), *self.pages.get(page as usize)?)))
+            .collect();
+        if !destinations.is_empty() {
+            destinations.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            let mut dests = Dictionary::new();
+            dests.set(
+                "Names",
+                destinations
+                    .into_iter()
+                    .flat_map(|(name, page)| {
+                        [
+                            Object::string_literal(name),
+                            vec![page.into(), "Fit".into()].into(),
+                        ]
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            names.set("Dests", dests);
+        }
+
+        if !names.is_empty() {
+            catalog.set("Names", names);
+        }
+
+        let mut pages = Dictionary::new();
+        pages.set("Type", "Pages");
+        pages.set("Count", self.page_count());
+        pages.set(
+            "Kids",
+            self.pages
+                .iter()
+                .map(|id| Object::Reference(*id))
+                .collect::<Vec<_>>(),
+        );
+
+        self.write_object(Self::PAGES_ID, &serialize(&Object::Dictionary(pages)))
+            .await?;
+        self.write_object(Self::CATALOG_ID, &serialize(&Object::Dictionary(catalog)))
+            .await?;
+
+        let xref_position = self.position;
+        let xref = self.xref_table();
+        self.write(&xref).await?;
+
+        let mut trailer = Dictionary::new();
+        trailer.set("Size", self.next_id);
+        trailer.set("Root", Self::CATALOG_ID);
+
+        let mut buf = b"trailer\n".to_vec();
+        buf.extend(serialize(&Object::Dictionary(trailer)));
+        write!(buf, "\nstartxref\n{xref_position}\n%%EOF\n")?;
+        self.write(&buf).await?;
+
+        self.out.flush().await?;
+        Ok(self.out)
+    }
+
+    /// Replaces resources of the new pages with already written ones where they are identical.
+    fn share_resources(&self, document: &mut Document, pages: &[ObjectId]) {
+        let mut replacements = HashMap::new();
+
+        // objects may only match once the objects they reference were replaced (e.g. images and their SMask)
+        loop {
+            let found: Vec<(ObjectId, ObjectId)> = document
+                .objects
+                .iter()
+                .filter(|(object_id, object)| {
+                    !pages.contains(object_id)
+                        && !replacements.contains_key(*object_id)
+                        && is_shareable(object)
+                })
+                .filter_map(|(object_id, object)| {
+                    let hash: [u8; 32] = Sha256::digest(serialize(object)).into();
+                    self.shared.get(&hash).map(|shared| (*object_id, *shared))
+                })
+                .collect();
+
+            if found.is_empty() {
+                break;
+            }
+
+            replacements.extend(found);
+            for object in document.objects.values_mut() {
+                replace_references(object, &replacements);
+            }
+        }
+
+        for object_id in replacements.keys() {
+            document.objects.remove(object_id);
+        }
+    }
+
+    /// Replaces the book relative destinations of internal links (see `links.rs`) with document wide ones.
+    fn resolve_destinations(&mut self, object: &mut Object) {
+        match object {
+            Object::Array(array) => array
+                .iter_mut()
+                .for_each(|item| self.resolve_destinations(item)),
+            Object::Dictionary(dict) => {
+                if let Ok(Object::String(name, _)) = dict.get_mut(b"Dest") {
+                    let page = std::str::from_utf8(name)
+                        .ok()
+                        .and_then(|name| name.strip_prefix(PAGE_DESTINATION_PREFIX))
+                        .and_then(|page| page.parse::<u16>().ok())
+                        .filter(|page| self.section.1.contains(page));
+
+                    if let Some(page) = page {
+                        let page = self.section.0 + u32::from(page - self.section.1.start());
+                        *name = destination_name(page).into_bytes();
+                        self.destinations.insert(page);
+                    }
+                }
+
+                dict.iter_mut()
+                    .for_each(|(_, value)| self.resolve_destinations(value));
+            }
+            _ => {}
+        }
+    }
+
+    fn xref_table(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        writeln!(buf, "xref").unwrap();
+        writeln!(buf, "0 1").unwrap();
+        write!(buf, "0000000000 65535 f\r\n").unwrap();
+
+        // ids of objects that were shared instead of written leave gaps, which split the table into sections
+        let mut sections: Vec<Vec<(u32, u64)>> = Vec::new();
+        for (&id, &offset) in &self.offsets {
+            match sections.last_mut() {
+                Some(section) if section.last().unwrap().0 + 1 == id => section.push((id, offset)),
+                _ => sections.push(vec![(id, offset)]),
+            }
+        }
+
+        for section in sections {
+            writeln!(buf, "{} {}", section[0].0, section.len()).unwrap();
+            for (_, offset) in section {
+                write!(buf, "{offset:010} 00000 n\r\n").unwrap();
+            }
+        }
+
+        buf
+    }
+
+    fn new_object_id(&mut self) -> ObjectId {
+        self.next_id += 1;
+        (self.next_id - 1, 0)
+    }
+
+    async fn write_object(&mut self, object_id: ObjectId, object: &[u8]) -> std::io::Result<()> {
+        self.offsets.insert(object_id.0, self.position);
+
+        self.write(format!("{} {} obj\n", object_id.0, object_id.1).as_bytes())
+            .await?;
+        self.write(object).await?;
+        self.write(b"\nendobj\n").await
+    }
+
+    async fn write(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        self.out.write_all(buf).await?;
+        self.position += buf.len() as u64;
+        Ok(())
+    }
+}
+
+/// Prefix of the document wide destinations written by `finish`.
+const DESTINATION_PREFIX: &str = "page";
+
+/// Name of the destination of the (zero-based) `page`.
+fn destination_name(page: u32) -> String {
+    format!("{DESTINATION_PREFIX}{}", page + 1)
+}
+
+/// Turns the destinations of pages taken from a file with a single book written by `PdfWriter`
+/// back into book relative ones, so `add_document` resolves them again within the new file.
+pub(crate) fn unresolve_destinations(document: &mut Document) {
+    fn unresolve(object: &mut Object) {
+        match object {
+            Object::Array(array) => array.iter_mut().for_each(unresolve),
+            Object::Dictionary(dict) => {
+                if let Ok(Object::String(name, _)) = dict.get_mut(b"Dest") {
+                    let page = std::str::from_utf8(name)
+                        .ok()
+                        .and_then(|name| name.strip_prefix(DESTINATION_PREFIX))
+                        .and_then(|page| page.parse::<u16>().ok());
+
+                    if let Some(page) = page {
+                        *name = format!("{PAGE_DESTINATION_PREFIX}{page}").into_bytes();
+                    }
+                }
+
+                dict.iter_mut().for_each(|(_, value)| unresolve(value));
+            }
+            _ => {}
+        }
+    }
+
+    document.objects.values_mut().for_each(unresolve);
+}
+
+/// Serializes an object to PDF syntax.
+fn serialize(object: &Object) -> Vec<u8> {
+    let mut buf = Vec::new();
+    serialize_into(object, &mut buf);
+    buf
+}
+
+fn serialize_into(object: &Object, buf: &mut Vec<u8>) {
+    match object {
+        Object::Null => buf.extend(b"null"),
+        Object::Boolean(value) => write!(buf, "{value}").unwrap(),
+        Object::Integer(value) => write!(buf, "{value}").unwrap(),
+        Object::Real(value) => write!(buf, "{value}").unwrap(),
+        Object::Name(name) => serialize_name(name, buf),
+        Object::String(text, StringFormat::Literal) => {
+            buf.push(b'(');
+            for &byte in text {
+                match byte {
+                    b'(' | b')' | b'\\' => buf.extend([b'\\', byte]),
+                    b'\r' => buf.extend(b"\\r"),
+                    _ => buf.push(byte),
+                }
+            }
+            buf.push(b')');
+        }
+        Object::String(text, StringFormat::Hexadecimal) => {
+            buf.push(b'<');
+            text.iter()
+                .for_each(|byte| write!(buf, "{byte:02X}").unwrap());
+            buf.push(b'>');
+        }
+        Object::Array(array) => {
+            buf.push(b'[');
+            for (i, item) in array.iter().enumerate() {
+                if i > 0 {
+                    buf.push(b' ');
+                }
+                serialize_into(item, buf);
+            }
+            buf.push(b']');
+        }
+        Object::Dictionary(dict) => serialize_dictionary(dict, buf),
+        Object::Stream(stream) => {
+            let mut dict = stream.dict.clone();
+            dict.set("Length", stream.content.len() as i64);
+
+            serialize_dictionary(&dict, buf);
+            buf.extend(b"\nstream\n");
+            buf.extend(&stream.content);
+            buf.extend(b"\nendstream");
+        }
+        Object::Reference(id) => write!(buf, "{} {} R", id.0, id.1).unwrap(),
+    }
+}
+
+fn serialize_name(name: &[u8], buf: &mut Vec<u8>) {
+    buf.push(b'/');
+    for &byte in name {
+        // white-space, delimiters and bytes outside of the printable range have to be escaped
+        if b" \t\n\r\x0C()<>[]{}/%#".contains(&byte) || !(33..=126).contains(&byte) {
+            write!(buf, "#{byte:02X}").unwrap();
+        } else {
+            buf.push(byte);
+        }
+    }
+}
+
+fn serialize_dictionary(dict: &Dictionary, buf: &mut Vec<u8>) {
+    buf.extend(b"<<");
+    for (key, value) in dict.iter() {
+        serialize_name(key, buf);
+        buf.push(b' ');
+        serialize_into(value, buf);
+    }
+    buf.extend(b">>");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::page_size::PageSize;
+    use crate::scraper::util::{extract_page, placeholder_page};
+
+    /// A page with a link to the (one-based) page `target` of its book.
+    fn page_linking_to(target: u16) -> Document {
+        let mut document = placeholder_page(PageSize::A4, &[]);
+        let page = *document.get_pages().values().next().unwrap();
+
+        let mut link = Dictionary::new();
+        link.set("Type", "Annot");
+        link.set("Subtype", "Link");
+        link.set("Rect", vec![0.into(), 0.into(), 10.into(), 10.into()]);
+        link.set(
+            "Dest",
+            Object::string_literal(format!("{PAGE_DESTINATION_PREFIX}{target}")),
+        );
+        document
+            .get_dictionary_mut(page)
+            .unwrap()
+            .set("Annots", vec![Object::Dictionary(link)]);
+        document
+    }
+
+    /// Name of the destination of the link on `page` and the page (one-based) the name tree resolves it to.
+    fn link_target(document: &Document, page: u32) -> (String, Option<u32>) {
+        let page_id = document.get_pages()[&page];
+        let annots = document
+            .get_dictionary(page_id)
+            .unwrap()
+            .get(b"Annots")
+            .unwrap()
+            .as_array()
+            .unwrap();
+        let name = annots[0]
+            .as_dict()
+            .unwrap()
+            .get(b"Dest")
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_vec();
+
+        let names = document
+            .catalog()
+            .unwrap()
+            .get(b"Names")
+            .and_then(Object::as_dict)
+            .unwrap()
+            .get(b"Dests")
+            .and_then(Object::as_dict)
+            .unwrap()
+            .get(b"Names")
+            .and_then(Object::as_array)
+            .unwrap();
+        let target = names
+            .chunks(2)
+            .find(|entry| entry[0].as_str().unwrap() == name)
+            .map(|entry| entry[1].as_array().unwrap()[0].as_reference().unwrap())
+            .and_then(|target| {
+                document
+                    .get_pages()
+                    .into_iter()
+                    .find(|(_, id)| *id == target)
+                    .map(|(number, _)| number)
+            });
+
+        (String::from_utf8(name).unwrap(), target)
+    }
+
+    async fn write(documents: Vec<Document>) -> Document {
+        let mut writer = PdfWriter::new(Vec::new()).await.unwrap();
+        for document in documents {
+            writer.add_document(document).await.unwrap();
+        }
+        Document::load_mem(&writer.finish().await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn resolves_links_within_the_section() {
+        let mut writer = PdfWriter::new(Vec::new()).await.unwrap();
+        writer
+            .add_document(placeholder_page(PageSize::A4, &[]))
+            .await
+            .unwrap();
+        writer.start_section(1..=2);
+        writer.add_document(page_linking_to(2)).await.unwrap();
+        writer
+            .add_document(placeholder_page(PageSize::A4, &[]))
+            .await
+            .unwrap();
+        let document = Document::load_mem(&writer.finish().await.unwrap()).unwrap();
+
+        assert_eq!(link_target(&document, 2), ("page3".into(), Some(3)));
+    }
+
+    #[tokio::test]
+    async fn resolves_links_of_reused_pages_again() {
+        let previous = write(vec![
+            placeholder_page(PageSize::A4, &[]),
+            page_linking_to(3),
+            placeholder_page(PageSize::A4, &[]),
+        ])
+        .await;
+
+        // the first page was dropped, so the link target moved
+        let mut writer = PdfWriter::new(Vec::new()).await.unwrap();
+        writer.start_section(2..=3);
+        for page in [2, 3] {
+            let mut document = extract_page(&previous, previous.get_pages()[&page]);
+            unresolve_destinations(&mut document);
+            writer.add_document(document).await.unwrap();
+        }
+        let document = Document::load_mem(&writer.finish().await.unwrap()).unwrap();
+
+        assert_eq!(link_target(&document, 1), ("page2".into(), Some(2)));
+    }
+
+    #[tokio::test]
+    async fn writes_loadable_document() {
+        let mut writer = PdfWriter::new(Vec::new()).await.unwrap();
+        for _ in 0..3 {
+            writer
+                .add_document(placeholder_page(PageSize::A4, &[]))
+                .await
+                .unwrap();
+        }
+        writer.add_bookmark("Start".into(), 0, None);
+        let buf = writer.finish().await.unwrap();
+
+        let document = Document::load_mem(&buf).unwrap();
+        assert_eq!(document.get_pages().len(), 3);
+        assert_eq!(document.get_toc().unwrap().toc[0].title, "Start");
+    }
+
+    #[tokio::test]
+    async fn xref_table_points_at_every_object() {
+        let mut writer = PdfWriter::new(Vec::new()).await.unwrap();
+        for lines in [["a".to_string()], ["b".to_string()]] {
+            writer
+                .add_document(placeholder_page(PageSize::A4, &lines))
+                .await
+                .unwrap();
+        }
+        let buf = writer.finish().await.unwrap();
+        // only the end of the file is text, the header contains binary
+        let tail = |position: usize| String::from_utf8(buf[position..].to_vec()).unwrap();
+
+        let xref_position: usize = tail(buf.len() - 64)
+            .rsplit_once("startxref\n")
+            .and_then(|(_, rest)| rest.lines().next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        let xref = tail(xref_position);
+        let mut lines = xref.lines().skip(1);
+
+        let mut checked = 0;
+        while let Some((first_id, count)) = lines.next().and_then(|line| line.split_once(' ')) {
+            if first_id == "trailer" || !first_id.chars().all(|c| c.is_ascii_digit()) {
+                break;
+            }
+            let (first_id, count): (u32, usize) =
+                (first_id.parse().unwrap(), count.parse().unwrap());
+
+            for (id, entry) in (first_id..).zip(lines.by_ref().take(count)) {
+                if entry.ends_with('f') {
+                    continue;
+                }
+                let offset: usize = entry[..10].parse().unwrap();
+                assert!(
+                    buf[offset..].starts_with(format!("{id} 0 obj").as_bytes()),
+                    "wrong offset of object {id}"
+                );
+                checked += 1;
+            }
+        }
+        assert_eq!(checked, writer_object_count(&buf));
+    }
+
+    #[tokio::test]
+    async fn shares_identical_resources() {
+        let document = write(vec![
+            placeholder_page(PageSize::A4, &["a".into()]),
+            placeholder_page(PageSize::A4, &["b".into()]),
+        ])
+        .await;
+
+        let fonts: Vec<_> = document
+            .get_pages()
+            .values()
+            .map(|page| {
+                document
+                    .get_dictionary(*page)
+                    .unwrap()
+                    .get_deref(b"Resources", &document)
+                    .and_then(Object::as_dict)
+                    .unwrap()
+                    .get(b"Font")
+                    .and_then(Object::as_dict)
+                    .unwrap()
+                    .get(b"F1")
+                    .and_then(Object::as_reference)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(fonts[0], fonts[1]);
+    }
+
+    /// Number of `obj` headers in a written file.
+    fn writer_object_count(buf: &[u8]) -> usize {
+        String::from_utf8_lossy(buf)
+            .lines()
+            .filter(|line| line.ends_with(" 0 obj"))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn sorts_attachment_names() {
+        let mut writer = PdfWriter::new(Vec::new()).await.unwrap();
+        writer
+            .add_document(placeholder_page(PageSize::A4, &[]))
+            .await
+            .unwrap();
+        for name in ["b.txt", "a.txt"] {
+            writer
+                .add_attachment(name, "text/plain", b"text".to_vec())
+                .await
+                .unwrap();
+        }
+        let document = Document::load_mem(&writer.finish().await.unwrap()).unwrap();
+
+        let names = document
+            .catalog()
+            .unwrap()
+            .get(b"Names")
+            .and_then(Object::as_dict)
+            .unwrap()
+            .get(b"EmbeddedFiles")
+            .and_then(Object::as_dict)
+            .unwrap()
+            .get(b"Names")
+            .and_then(Object::as_array)
+            .unwrap();
+        let names: Vec<_> = names
+            .chunks(2)
+            .map(|entry| lopdf::decode_text_string(&entry[0]).unwrap())
+            .collect();
+        assert_eq!(names, ["a.txt", "b.txt"]);
+    }
+}
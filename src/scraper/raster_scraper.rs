@@ -0,0 +1,147 @@
+use crate::buffered_response::BufferedResponse;
+use crate::error::ScraperError;
+use crate::http::HttpClient;
+use crate::output_profile::OutputProfile;
+use crate::page::{Page, PageResource};
+use crate::page_size::PageSize;
+use crate::scraper::base_scraper::BaseScraper;
+use crate::scraper::util::image_to_pdf;
+use crate::trace_event;
+use async_trait::async_trait;
+use image::ImageReader;
+use lopdf::Document;
+use reqwest::{RequestBuilder, StatusCode};
+use std::fmt::Debug;
+use std::io::Cursor;
+
+/// For books whose pages are served as bitmap images instead of svgs.
+///
+/// Implementors also have to implement `Scraper`, by forwarding to `RasterScraper::fetch_page_pdf`,
+/// `RasterScraper::fetch_page_source`, `RasterScraper::fetch_page_size` and `RasterScraper::fetch_page`.
+#[async_trait]
+pub trait RasterScraper: BaseScraper + Sync + Send + Debug {
+    /// Resolution assumed if the viewer doesn't specify the size of a page.
+    const FALLBACK_DPI: f32 = 150.0;
+
+    /// Requests for the image of a page, ordered from the highest to the lowest resolution.
+    fn get_page_image_requests(&self, page: u16) -> Vec<RequestBuilder>;
+
+    /// Size of the page in points (1/72 inch), if the viewer specifies it.
+    fn get_page_size(&self, page: u16) -> Option<(f32, f32)>;
+
+    /// Sends the requests of `get_page_image_requests`.
+    fn client(&self) -> &HttpClient;
+
+    /// Downloads the image with the highest available resolution.
+    async fn get_page_image(&self, page: u16) -> Result<BufferedResponse, reqwest::Error> {
+        Ok(self.get_best_page_image(page).await?.0)
+    }
+
+    /// Like `get_page_image`, but also tells if the image has a lower resolution than the first request.
+    /// Only images that don't exist (404) are replaced by the next request, any other error is returned.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(page = page)))]
+    async fn get_best_page_image(
+        &self,
+        page: u16,
+    ) -> Result<(BufferedResponse, bool), reqwest::Error> {
+        let mut requests = self.get_page_image_requests(page).into_iter().peekable();
+        let mut degraded = false;
+
+        while let Some(request) = requests.next() {
+            let resp = self.client().send(request).await?;
+            if resp.status() == StatusCode::NOT_FOUND && requests.peek().is_some() {
+                trace_event!(WARN, url = %resp.url(), "page image doesn't exist, trying a lower resolution");
+                degraded = true;
+                continue;
+            }
+
+            resp.error_for_status_ref()?;
+            return Ok((resp, degraded));
+        }
+
+        panic!("no image requests for page {page}")
+    }
+
+    /// Wraps the page image in a page of the same size.
+    async fn fetch_page_pdf(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<Vec<u8>, ScraperError> {
+        let resp = self.get_page_image(page).await?;
+        self.image_to_pdf(page, &resp, profile)
+    }
+
+    /// The page image is the only resource of the page.
+    async fn fetch_page(&self, page: u16, profile: &OutputProfile) -> Result<Page, ScraperError> {
+        let (resp, degraded) = self.get_best_page_image(page).await?;
+        let mut pdf = Document::load_from(Cursor::new(self.image_to_pdf(page, &resp, profile)?))?;
+        profile.apply_paper_size(&mut pdf);
+
+        let resource = PageResource::new(
+            resp.url().clone(),
+            resp.headers()
+                .get("Content-Type")
+                .and_then(|content_type| content_type.to_str().ok())
+                .map(str::to_owned),
+            resp.body(),
+        );
+
+        Ok(Page::new(page, self.get_page_label(page), pdf)
+            .with_source_url(self.get_page_url(page))
+            .with_resources(vec![resource])
+            .with_degraded(degraded))
+    }
+
+    /// Wraps a page image in a page of the same size.
+    fn image_to_pdf(
+        &self,
+        page: u16,
+        resp: &BufferedResponse,
+        profile: &OutputProfile,
+    ) -> Result<Vec<u8>, ScraperError> {
+        let (width, height) = match self.get_page_size(page) {
+            Some(size) => size,
+            None => Self::image_page_size(resp)?,
+        };
+
+        let content_type = resp
+            .headers()
+            .get("Content-Type")
+            .and_then(|content_type| content_type.to_str().ok())
+            .unwrap_or("image/jpeg")
+            .to_owned();
+        let (content_type, data) =
+            profile.process_image(&content_type, resp.bytes(), Some((width, height)));
+
+        image_to_pdf(&content_type, &data, width, height)
+    }
+
+    async fn fetch_page_source(&self, page: u16) -> Result<Vec<u8>, reqwest::Error> {
+        Ok(self.get_page_image(page).await?.bytes().to_vec())
+    }
+
+    /// Only downloads the page image if the viewer doesn't specify the size.
+    async fn fetch_page_size(&self, page: u16) -> Result<PageSize, ScraperError> {
+        let (width, height) = match self.get_page_size(page) {
+            Some(size) => size,
+            None => Self::image_page_size(&self.get_page_image(page).await?)?,
+        };
+
+        Ok(PageSize::new(width, height))
+    }
+
+    /// Size of a page image at `FALLBACK_DPI`.
+    fn image_page_size(resp: &BufferedResponse) -> Result<(f32, f32), ScraperError> {
+        let (width, height) = ImageReader::new(Cursor::new(resp.bytes()))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok())
+            .ok_or_else(|| ScraperError::InvalidImage(resp.url().clone()))?;
+
+        Ok((
+            width as f32 * 72.0 / Self::FALLBACK_DPI,
+            height as f32 * 72.0 / Self::FALLBACK_DPI,
+        ))
+    }
+}
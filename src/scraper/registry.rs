@@ -0,0 +1,312 @@
+use crate::buffered_response::BufferedResponse;
+use crate::error::ScraperError;
+use crate::http::HttpClient;
+use crate::scraper::base_scraper::BaseScraper;
+use crate::scraper::scraper_structs::digi4school::Digi4SchoolScraper;
+use crate::scraper::scraper_structs::digi4school_raster::Digi4SchoolRasterScraper;
+use crate::scraper::scraper_structs::native_pdf::NativePdfScraper;
+use crate::scraper::scraper_trait::Scraper;
+use reqwest::Url;
+use std::sync::{Arc, LazyLock, RwLock};
+
+pub type ScraperConstructor =
+    fn(Arc<BufferedResponse>, Arc<HttpClient>) -> Result<Box<dyn Scraper>, ScraperError>;
+
+enum UrlMatcher {
+    /// Either an exact host or `*.` followed by a domain, which matches all of its subdomains.
+    Host(String),
+    Predicate(Box<dyn Fn(&Url) -> bool + Send + Sync>),
+    /// Only the content probe decides.
+    Any,
+}
+
+impl UrlMatcher {
+    fn matches(&self, url: &Url) -> bool {
+        match self {
+            UrlMatcher::Host(pattern) => {
+                let Some(host) = url.host_str() else {
+                    return false;
+                };
+
+                match pattern.strip_prefix("*.") {
+                    Some(domain) => host
+                        .strip_suffix(domain)
+                        .is_some_and(|subdomain| subdomain.ends_with('.')),
+                    None => host.eq_ignore_ascii_case(pattern),
+                }
+            }
+            UrlMatcher::Predicate(predicate) => predicate(url),
+            UrlMatcher::Any => true,
+        }
+    }
+}
+
+struct Entry {
+    url: UrlMatcher,
+    /// See `BaseScraper::probe`.
+    probe: Option<fn(&BufferedResponse) -> bool>,
+    constructor: ScraperConstructor,
+}
+
+impl Entry {
+    fn matches(&self, resp: &BufferedResponse) -> bool {
+        self.url.matches(resp.url()) && self.probe.is_none_or(|probe| probe(resp))
+    }
+}
+
+/// Decides which scraper handles a volume, based on the URL its viewer is served from
+/// or, for unknown hosts, the content of its first response.
+/// Applications can register their own scrapers to support additional providers.
+///
+/// Scrapers are tried in reverse registration order,
+/// so registered scrapers take precedence over the built-in ones.
+/// Scrapers registered with only a content probe are tried if no host or predicate matched.
+pub struct ScraperRegistry {
+    scrapers: RwLock<Vec<Entry>>,
+}
+
+impl ScraperRegistry {
+    /// A registry containing only the built-in scrapers.
+    pub fn new() -> Self {
+        let registry = Self {
+            scrapers: RwLock::default(),
+        };
+
+        for host in [Digi4SchoolScraper::DOMAIN, "a.hpthek.at"] {
+            registry.register_host::<Digi4SchoolScraper>(host);
+            // some books on the same hosts are served as images instead of svgs
+            registry.register_host_with_probe::<Digi4SchoolRasterScraper>(host);
+            // volumes linking their original files, preferred over rendering their viewer
+            registry.register_host_with_probe::<NativePdfScraper>(host);
+        }
+        registry.register_probe::<Digi4SchoolScraper>();
+        registry.register_probe::<Digi4SchoolRasterScraper>();
+        registry.register_probe::<NativePdfScraper>();
+
+        registry
+    }
+
+    /// The registry used by `Volume::get_scraper`.
+    pub fn global() -> &'static Self {
+        static REGISTRY: LazyLock<ScraperRegistry> = LazyLock::new(ScraperRegistry::new);
+        &REGISTRY
+    }
+
+    /// Handles all volumes served from `pattern`.
+    /// `*.example.com` matches all subdomains of `example.com`.
+    pub fn register_host<S: BaseScraper>(&self, pattern: &str) {
+        self.register::<S>(UrlMatcher::Host(pattern.to_ascii_lowercase()), None);
+    }
+
+    /// Like `register_host`, but only handles volumes which are also recognized by `S::probe`.
+    /// Useful if a host serves multiple kinds of viewers.
+    pub fn register_host_with_probe<S: BaseScraper>(&self, pattern: &str) {
+        self.register::<S>(
+            UrlMatcher::Host(pattern.to_ascii_lowercase()),
+            Some(S::probe),
+        );
+    }
+
+    /// Handles all volumes whose URL satisfies `predicate`.
+    pub fn register_predicate<S: BaseScraper>(
+        &self,
+        predicate: impl Fn(&Url) -> bool + Send + Sync + 'static,
+    ) {
+        self.register::<S>(UrlMatcher::Predicate(Box::new(predicate)), None);
+    }
+
+    /// Handles all volumes recognized by `S::probe`, no matter which host they are served from.
+    pub fn register_probe<S: BaseScraper>(&self) {
+        self.register::<S>(UrlMatcher::Any, Some(S::probe));
+    }
+
+    /// Takes the first response of the volume (after following all `LTIForm` redirects).
+    pub fn get_constructor(
+        &self,
+        resp: &BufferedResponse,
+    ) -> Result<ScraperConstructor, ScraperError> {
+        let scrapers = self.scrapers.read().unwrap();
+        let (by_content, by_url): (Vec<&Entry>, Vec<&Entry>) = scrapers
+            .iter()
+            .rev()
+            .partition(|entry| matches!(entry.url, UrlMatcher::Any));
+
+        by_url
+            .into_iter()
+            .chain(by_content)
+            .find(|entry| entry.matches(resp))
+            .map(|entry| entry.constructor)
+            .ok_or_else(|| ScraperError::UnsupportedProvider(resp.url().clone()))
+    }
+
+    fn register<S: BaseScraper>(
+        &self,
+        url: UrlMatcher,
+        probe: Option<fn(&BufferedResponse) -> bool>,
+    ) {
+        self.scrapers.write().unwrap().push(Entry {
+            url,
+            probe,
+            constructor: S::new_scraper,
+        });
+    }
+}
+
+impl Default for ScraperRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use reqwest::ResponseBuilderExt;
+
+    /// Tells which scraper was constructed by its page count.
+    #[derive(Debug)]
+    struct NumberedScraper<const N: u16>;
+
+    #[async_trait]
+    impl<const N: u16> BaseScraper for NumberedScraper<N> {
+        fn new_scraper(
+            _resp: Arc<BufferedResponse>,
+            _client: Arc<HttpClient>,
+        ) -> Result<Box<dyn Scraper>, ScraperError> {
+            Ok(Box::new(Self))
+        }
+
+        fn probe(resp: &BufferedResponse) -> bool {
+            resp.text().contains(&format!("scraper {N}"))
+        }
+
+        async fn fetch_page_count(&self) -> Result<u16, ScraperError> {
+            Ok(N)
+        }
+    }
+
+    #[async_trait]
+    impl<const N: u16> Scraper for NumberedScraper<N> {
+        async fn fetch_page_raw_pdf(
+            &self,
+            _page: u16,
+            _profile: &crate::output_profile::OutputProfile,
+        ) -> Result<Vec<u8>, ScraperError> {
+            unreachable!()
+        }
+
+        async fn fetch_page_source(&self, _page: u16) -> Result<Vec<u8>, ScraperError> {
+            unreachable!()
+        }
+    }
+
+    async fn response(url: &str, body: &'static str) -> BufferedResponse {
+        let resp = http::Response::builder()
+            .url(Url::parse(url).unwrap())
+            .body(body)
+            .unwrap();
+        BufferedResponse::new(resp.into()).await.unwrap()
+    }
+
+    /// The number of the scraper chosen for `resp`.
+    async fn chosen(registry: &ScraperRegistry, resp: BufferedResponse) -> Option<u16> {
+        let constructor = registry.get_constructor(&resp).ok()?;
+        let scraper = constructor(Arc::new(resp), Arc::new(HttpClient::default())).ok()?;
+        Some(scraper.fetch_page_count().await.unwrap())
+    }
+
+    fn empty_registry() -> ScraperRegistry {
+        ScraperRegistry {
+            scrapers: RwLock::default(),
+        }
+    }
+
+    #[test]
+    fn matches_hosts_and_their_subdomains() {
+        let url = |url: &str| Url::parse(url).unwrap();
+
+        let exact = UrlMatcher::Host("example.com".into());
+        assert!(exact.matches(&url("https://example.com/book")));
+        assert!(exact.matches(&url("https://EXAMPLE.com/book")));
+        assert!(!exact.matches(&url("https://a.example.com/book")));
+
+        let subdomains = UrlMatcher::Host("*.example.com".into());
+        assert!(subdomains.matches(&url("https://a.example.com/book")));
+        assert!(subdomains.matches(&url("https://a.b.example.com/book")));
+        assert!(!subdomains.matches(&url("https://example.com/book")));
+        assert!(!subdomains.matches(&url("https://notexample.com/book")));
+    }
+
+    #[tokio::test]
+    async fn prefers_later_registrations() {
+        let registry = empty_registry();
+        registry.register_host::<NumberedScraper<1>>("example.com");
+        registry.register_predicate::<NumberedScraper<2>>(|url| url.path().starts_with("/ebook"));
+
+        assert_eq!(
+            chosen(&registry, response("https://example.com/ebook/1", "").await).await,
+            Some(2)
+        );
+        assert_eq!(
+            chosen(&registry, response("https://example.com/other", "").await).await,
+            Some(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn probes_hosts_registered_with_a_probe() {
+        let registry = empty_registry();
+        registry.register_host::<NumberedScraper<1>>("example.com");
+        registry.register_host_with_probe::<NumberedScraper<2>>("example.com");
+
+        let resp = response("https://example.com/ebook/1", "scraper 2").await;
+        assert_eq!(chosen(&registry, resp).await, Some(2));
+        let resp = response("https://example.com/ebook/1", "scraper 3").await;
+        assert_eq!(chosen(&registry, resp).await, Some(1));
+    }
+
+    #[tokio::test]
+    async fn probes_unknown_hosts_after_matching_urls() {
+        let registry = empty_registry();
+        registry.register_probe::<NumberedScraper<1>>();
+        registry.register_host::<NumberedScraper<2>>("example.com");
+
+        let resp = response("https://example.com/ebook/1", "scraper 1").await;
+        assert_eq!(chosen(&registry, resp).await, Some(2));
+        let resp = response("https://books.example.org/1", "scraper 1").await;
+        assert_eq!(chosen(&registry, resp).await, Some(1));
+        let resp = response("https://books.example.org/1", "scraper 3").await;
+        assert_eq!(chosen(&registry, resp).await, None);
+    }
+
+    #[tokio::test]
+    async fn prefers_linked_pdfs_over_viewers() {
+        let registry = ScraperRegistry::new();
+        let viewer = r#"<script>IDRViewer.makeNavBar(120,'.jpg',</script><a href="original.pdf">Download</a>"#;
+
+        for url in [
+            "https://a.digi4school.at/ebook/123/",
+            "https://books.example.org/123/",
+        ] {
+            let resp = Arc::new(response(url, viewer).await);
+            let constructor = registry.get_constructor(&resp).unwrap();
+            let scraper = constructor(resp, Arc::new(HttpClient::default())).unwrap();
+            assert!(
+                format!("{scraper:?}").starts_with("NativePdfScraper"),
+                "{scraper:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_hosts() {
+        let registry = ScraperRegistry::new();
+        let resp = response("https://example.com/ebook/1", "<html></html>").await;
+
+        assert!(matches!(
+            registry.get_constructor(&resp),
+            Err(ScraperError::UnsupportedProvider(url)) if url.host_str() == Some("example.com")
+        ));
+    }
+}
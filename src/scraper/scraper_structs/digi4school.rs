@@ -1,10 +1,14 @@
 use crate::buffered_response::BufferedResponse;
+use crate::error::ScraperError;
+use crate::http::HttpClient;
+use crate::page::Chapter;
 use crate::regex;
 use crate::scraper::base_scraper::BaseScraper;
 use crate::scraper::scraper_trait::Scraper;
 use crate::scraper::svg_scraper::SvgScraper;
 use async_trait::async_trait;
-use reqwest::{Client, RequestBuilder, Url};
+use regex::Regex;
+use reqwest::Url;
 use std::str::FromStr;
 use std::sync::Arc;
 
@@ -12,15 +16,18 @@ use std::sync::Arc;
 pub struct Digi4SchoolScraper {
     base_url: Url,
     page_count: u16,
+    /// Printed page numbers, if the viewer config specifies them.
+    page_labels: Vec<String>,
+    chapters: Vec<Chapter>,
 
-    client: Arc<Client>,
+    client: Arc<HttpClient>,
 }
 
 impl Digi4SchoolScraper {
     pub const DOMAIN: &'static str = "a.digi4school.at";
 
     /// Takes first response from the `LTIForm` redirects as an input
-    fn get_page_count(resp: &BufferedResponse) -> u16 {
+    pub(crate) fn get_page_count(resp: &BufferedResponse) -> u16 {
         macro_rules! panic_closure {
             () => {|| panic!(
                 "{} didn't behave as expected. Couldn't find the page number\nResponse:\n{}",
@@ -29,8 +36,8 @@ impl Digi4SchoolScraper {
         }
 
         u16::from_str(
-            regex!(r"IDRViewer\.makeNavBar\((\d+),'\.jpg'")
-                .captures(&resp.text())
+            Self::nav_bar_regex()
+                .captures(resp.text())
                 .unwrap_or_else(panic_closure!())
                 .get(1)
                 .unwrap_or_else(panic_closure!())
@@ -38,6 +45,39 @@ impl Digi4SchoolScraper {
         )
         .unwrap()
     }
+
+    /// Reads the `pageLabels` of the viewer config, empty if the pages are simply numbered.
+    pub(crate) fn get_page_labels(resp: &BufferedResponse) -> Vec<String> {
+        let text = resp.text();
+        let Some(labels) = regex!(r#""pageLabels"\s*:\s*\[([^\]]*)\]"#).captures(text) else {
+            return Vec::new();
+        };
+
+        regex!(r#""((?:[^"\\]|\\.)*)""#)
+            .captures_iter(&labels[1])
+            .map(|c| c[1].replace("\\\"", "\""))
+            .collect()
+    }
+
+    /// Reads the `bookmarks` of the viewer config, empty if the volume has no table of contents.
+    pub(crate) fn get_chapters(resp: &BufferedResponse) -> Vec<Chapter> {
+        let text = resp.text();
+        let Some(start) = regex!(r#""bookmarks"\s*:\s*"#).find(text) else {
+            return Vec::new();
+        };
+
+        // the config continues after the list, so only the list itself is parsed
+        serde_json::Deserializer::from_str(&text[start.end()..])
+            .into_iter::<Vec<Chapter>>()
+            .next()
+            .and_then(Result::ok)
+            .unwrap_or_default()
+    }
+
+    /// The page count can only be read if this is found, so it identifies the viewer as well.
+    fn nav_bar_regex() -> &'static Regex {
+        regex!(r"IDRViewer\.makeNavBar\((\d+),'\.jpg'")
+    }
 }
 
 #[async_trait]
@@ -50,24 +90,43 @@ impl SvgScraper for Digi4SchoolScraper {
         );
 
         let url = format!("{}/{page}.svg", self.base_url);
-        Ok(self.client.get(url).send().await?.text().await?)
+        Ok(self
+            .client
+            .send(self.client.get(url))
+            .await?
+            .text()
+            .to_owned())
     }
 
-    fn get_image_request(&self, relative_url: &str) -> RequestBuilder {
+    async fn get_image(&self, relative_url: &str) -> Result<BufferedResponse, reqwest::Error> {
         let url = format!("{}/{}", self.base_url, relative_url);
-        self.client.get(url)
+        self.client.send(self.client.get(url)).await
+    }
+
+    /// The thumbnail the viewer shows in its page overview.
+    async fn get_page_fallback_image(
+        &self,
+        page: u16,
+    ) -> Result<Option<BufferedResponse>, reqwest::Error> {
+        let url = format!("{}/thumbnails/{page}.jpg", self.base_url);
+        let resp = self.client.send(self.client.get(url)).await?;
+
+        Ok(resp.status().is_success().then_some(resp))
     }
 }
 
 #[async_trait]
 impl BaseScraper for Digi4SchoolScraper {
-    fn new_scraper(resp: Arc<BufferedResponse>, client: Arc<Client>) -> Box<dyn Scraper>
+    fn new_scraper(
+        resp: Arc<BufferedResponse>,
+        client: Arc<HttpClient>,
+    ) -> Result<Box<dyn Scraper>, ScraperError>
     where
         Self: Sized,
     {
         let base_url = resp.url().as_str().trim_end_matches("/index.html");
 
-        Box::new(Digi4SchoolScraper {
+        Ok(Box::new(Digi4SchoolScraper {
             base_url: Url::parse(base_url).unwrap_or_else(|_| {
                 panic!(
                     "Bad base_url supplied: {}.\nResponse URL: {}",
@@ -76,12 +135,67 @@ impl BaseScraper for Digi4SchoolScraper {
                 )
             }),
             page_count: Self::get_page_count(&resp),
+            page_labels: Self::get_page_labels(&resp),
+            chapters: Self::get_chapters(&resp),
 
             client,
-        })
+        }))
+    }
+
+    fn probe(resp: &BufferedResponse) -> bool
+    where
+        Self: Sized,
+    {
+        Self::nav_bar_regex().is_match(resp.text())
     }
 
-    async fn fetch_page_count(&self) -> Result<u16, reqwest::Error> {
+    async fn fetch_page_count(&self) -> Result<u16, ScraperError> {
         Ok(self.page_count)
     }
+
+    fn get_page_label(&self, page: u16) -> Option<String> {
+        let index = usize::from(page).checked_sub(1)?;
+        self.page_labels.get(index).cloned()
+    }
+
+    fn get_chapters(&self) -> Vec<Chapter> {
+        self.chapters.clone()
+    }
+
+    fn get_page_url(&self, page: u16) -> Option<Url> {
+        Url::parse(&format!("{}/{page}.svg", self.base_url)).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffered_response::tests::response;
+
+    #[tokio::test]
+    async fn reads_nested_bookmarks() {
+        let resp = response(
+            "text/html",
+            r#"var config = {"pageLabels":[],"bookmarks":[{"title":"1 Zahlen","page":3,"zoom":"XYZ 0 0 0","children":[{"title":"1.1 Brüche","page":4}]},{"title":"2 Terme","page":9}],"pageType":"svg"};"#,
+        )
+        .await;
+
+        assert_eq!(
+            Digi4SchoolScraper::get_chapters(&resp),
+            [
+                Chapter::new(
+                    "1 Zahlen",
+                    3,
+                    vec![Chapter::new("1.1 Brüche", 4, Vec::new())]
+                ),
+                Chapter::new("2 Terme", 9, Vec::new()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_bookmarks_without_config() {
+        let resp = response("text/html", "IDRViewer.makeNavBar(2,'.jpg',").await;
+        assert!(Digi4SchoolScraper::get_chapters(&resp).is_empty());
+    }
 }
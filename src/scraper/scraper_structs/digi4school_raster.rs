@@ -0,0 +1,276 @@
+use crate::buffered_response::BufferedResponse;
+use crate::error::ScraperError;
+use crate::http::HttpClient;
+use crate::output_profile::OutputProfile;
+use crate::page::{Chapter, Page};
+use crate::page_size::PageSize;
+use crate::regex;
+use crate::scraper::base_scraper::BaseScraper;
+use crate::scraper::raster_scraper::RasterScraper;
+use crate::scraper::scraper_structs::digi4school::Digi4SchoolScraper;
+use crate::scraper::scraper_trait::Scraper;
+use async_trait::async_trait;
+use reqwest::{RequestBuilder, Url};
+use std::sync::Arc;
+
+/// Same viewer as `Digi4SchoolScraper`, but the pages are images.
+#[derive(Debug)]
+pub struct Digi4SchoolRasterScraper {
+    base_url: Url,
+    page_count: u16,
+    /// File extension of the page images (e.g. `jpg`).
+    page_type: String,
+    /// Size of every page in points, if the viewer config specifies it.
+    page_sizes: Vec<(f32, f32)>,
+    page_labels: Vec<String>,
+    chapters: Vec<Chapter>,
+
+    client: Arc<HttpClient>,
+}
+
+impl Digi4SchoolRasterScraper {
+    fn get_page_type(resp: &BufferedResponse) -> Option<String> {
+        regex!(r#""pageType"\s*:\s*"(jpg|jpeg|png)""#)
+            .captures(resp.text())
+            .map(|c| c[1].to_string())
+    }
+
+    /// Reads the `bounds` of the viewer config, which contains the size of every page.
+    fn get_page_sizes(resp: &BufferedResponse) -> Vec<(f32, f32)> {
+        let text = resp.text();
+        let Some(bounds) =
+            regex!(r#""bounds"\s*:\s*\[((?:\[[\d.]+,[\d.]+\],?)*)\]"#).captures(text)
+        else {
+            return Vec::new();
+        };
+
+        regex!(r"\[([\d.]+),([\d.]+)\]")
+            .captures_iter(&bounds[1])
+            .filter_map(|c| Some((c[1].parse().ok()?, c[2].parse().ok()?)))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl RasterScraper for Digi4SchoolRasterScraper {
+    fn get_page_image_requests(&self, page: u16) -> Vec<RequestBuilder> {
+        assert!(
+            page <= self.page_count,
+            "tried downloading invalid page: {page}/{}",
+            self.page_count
+        );
+
+        vec![
+            self.client
+                .get(format!("{}/{page}.{}", self.base_url, self.page_type)),
+            // the thumbnails are always available, but only as a last resort
+            self.client
+                .get(format!("{}/thumbnails/{page}.jpg", self.base_url)),
+        ]
+    }
+
+    fn get_page_size(&self, page: u16) -> Option<(f32, f32)> {
+        let index = usize::from(page).checked_sub(1)?;
+        self.page_sizes.get(index).copied()
+    }
+
+    fn client(&self) -> &HttpClient {
+        &self.client
+    }
+}
+
+#[async_trait]
+impl Scraper for Digi4SchoolRasterScraper {
+    async fn fetch_page_raw_pdf(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<Vec<u8>, ScraperError> {
+        RasterScraper::fetch_page_pdf(self, page, profile).await
+    }
+
+    async fn fetch_page_source(&self, page: u16) -> Result<Vec<u8>, ScraperError> {
+        Ok(RasterScraper::fetch_page_source(self, page).await?)
+    }
+
+    async fn fetch_page_size(&self, page: u16) -> Result<PageSize, ScraperError> {
+        RasterScraper::fetch_page_size(self, page).await
+    }
+
+    async fn fetch_page(&self, page: u16, profile: &OutputProfile) -> Result<Page, ScraperError> {
+        RasterScraper::fetch_page(self, page, profile).await
+    }
+}
+
+#[async_trait]
+impl BaseScraper for Digi4SchoolRasterScraper {
+    fn new_scraper(
+        resp: Arc<BufferedResponse>,
+        client: Arc<HttpClient>,
+    ) -> Result<Box<dyn Scraper>, ScraperError>
+    where
+        Self: Sized,
+    {
+        let base_url = resp.url().as_str().trim_end_matches("/index.html");
+
+        Ok(Box::new(Digi4SchoolRasterScraper {
+            base_url: Url::parse(base_url).unwrap_or_else(|_| {
+                panic!(
+                    "Bad base_url supplied: {}.\nResponse URL: {}",
+                    base_url,
+                    resp.url()
+                )
+            }),
+            page_count: Digi4SchoolScraper::get_page_count(&resp),
+            page_type: Self::get_page_type(&resp).ok_or_else(|| {
+                ScraperError::UnexpectedViewer(resp.url().clone(), "the pages aren't images")
+            })?,
+            page_sizes: Self::get_page_sizes(&resp),
+            page_labels: Digi4SchoolScraper::get_page_labels(&resp),
+            chapters: Digi4SchoolScraper::get_chapters(&resp),
+
+            client,
+        }))
+    }
+
+    /// The viewer config names the format of the pages.
+    fn probe(resp: &BufferedResponse) -> bool
+    where
+        Self: Sized,
+    {
+        Digi4SchoolScraper::probe(resp) && Self::get_page_type(resp).is_some()
+    }
+
+    async fn fetch_page_count(&self) -> Result<u16, ScraperError> {
+        Ok(self.page_count)
+    }
+
+    fn get_page_label(&self, page: u16) -> Option<String> {
+        let index = usize::from(page).checked_sub(1)?;
+        self.page_labels.get(index).cloned()
+    }
+
+    fn get_chapters(&self) -> Vec<Chapter> {
+        self.chapters.clone()
+    }
+
+    fn get_page_url(&self, page: u16) -> Option<Url> {
+        Url::parse(&format!("{}/{page}.{}", self.base_url, self.page_type)).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::replay::Replay;
+    use image::{ImageFormat, RgbImage};
+    use reqwest::{Client, Method, StatusCode};
+    use std::io::Cursor;
+
+    const BASE_URL: &str = "https://a.digi4school.at/ebook/1234";
+
+    fn png() -> Vec<u8> {
+        let mut buf = Cursor::new(Vec::new());
+        RgbImage::new(4, 4)
+            .write_to(&mut buf, ImageFormat::Png)
+            .unwrap();
+        buf.into_inner()
+    }
+
+    fn scraper(replay: Replay) -> Digi4SchoolRasterScraper {
+        Digi4SchoolRasterScraper {
+            base_url: Url::parse(BASE_URL).unwrap(),
+            page_count: 2,
+            page_type: "png".into(),
+            page_sizes: Vec::new(),
+            page_labels: Vec::new(),
+            chapters: Vec::new(),
+            client: Arc::new(HttpClient::new(Client::new()).with_transport(Arc::new(replay))),
+        }
+    }
+
+    fn add(replay: &mut Replay, path: &str, status: StatusCode) {
+        let url = Url::parse(&format!("{BASE_URL}/{path}")).unwrap();
+        replay.add_response(Method::GET, url, status, "image/png", png());
+    }
+
+    #[tokio::test]
+    async fn uses_full_resolution_image() {
+        let mut replay = Replay::new();
+        add(&mut replay, "1.png", StatusCode::OK);
+        add(&mut replay, "thumbnails/1.jpg", StatusCode::OK);
+
+        let page = Scraper::fetch_page(&scraper(replay), 1, &OutputProfile::default())
+            .await
+            .unwrap();
+        assert!(!page.degraded());
+        assert_eq!(page.resources()[0].url().path(), "/ebook/1234/1.png");
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_thumbnail_if_image_is_missing() {
+        let mut replay = Replay::new();
+        add(&mut replay, "thumbnails/1.jpg", StatusCode::OK);
+
+        let page = Scraper::fetch_page(&scraper(replay), 1, &OutputProfile::default())
+            .await
+            .unwrap();
+        assert!(page.degraded());
+        assert_eq!(
+            page.resources()[0].url().path(),
+            "/ebook/1234/thumbnails/1.jpg"
+        );
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_not_hidden_by_thumbnail() {
+        let mut replay = Replay::new();
+        add(&mut replay, "1.png", StatusCode::INTERNAL_SERVER_ERROR);
+        add(&mut replay, "thumbnails/1.jpg", StatusCode::OK);
+
+        let error = Scraper::fetch_page(&scraper(replay), 1, &OutputProfile::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ScraperError::Request(_)), "{error:?}");
+    }
+
+    #[tokio::test]
+    async fn invalid_image_is_an_error() {
+        let mut replay = Replay::new();
+        let url = Url::parse(&format!("{BASE_URL}/1.png")).unwrap();
+        replay.add_response(Method::GET, url, StatusCode::OK, "image/png", "not a png");
+
+        let error = Scraper::fetch_page_size(&scraper(replay), 1)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ScraperError::InvalidImage(_)), "{error:?}");
+    }
+
+    #[test]
+    fn page_zero_has_no_size_or_label() {
+        let mut scraper = scraper(Replay::new());
+        scraper.page_sizes = vec![(595.0, 842.0)];
+        scraper.page_labels = vec!["i".into()];
+
+        assert_eq!(scraper.get_page_size(0), None);
+        assert_eq!(scraper.get_page_label(0), None);
+        assert_eq!(scraper.get_page_label(1).as_deref(), Some("i"));
+    }
+
+    #[tokio::test]
+    async fn svg_viewer_is_an_error() {
+        let resp = crate::buffered_response::tests::response(
+            "text/html",
+            r#"<script>var config = {"pagecount":2,"pageType":"svg"}; IDRViewer.makeNavBar(2,'.jpg','.svg');</script>"#,
+        )
+        .await;
+
+        let error =
+            Digi4SchoolRasterScraper::new_scraper(Arc::new(resp), Arc::new(HttpClient::default()))
+                .unwrap_err();
+        assert!(
+            matches!(error, ScraperError::UnexpectedViewer(..)),
+            "{error:?}"
+        );
+    }
+}
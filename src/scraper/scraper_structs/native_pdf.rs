@@ -0,0 +1,270 @@
+use crate::buffered_response::BufferedResponse;
+use crate::digi4school::attachment::Attachment;
+use crate::error::ScraperError;
+use crate::http::HttpClient;
+use crate::manifest::{PageManifest, VolumeManifest};
+use crate::output_profile::OutputProfile;
+use crate::scraper::base_scraper::BaseScraper;
+use crate::scraper::pdf_writer::PdfWriter;
+use crate::scraper::scraper_trait::Scraper;
+use crate::scraper::util::extract_page;
+use async_trait::async_trait;
+use lopdf::Document;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use std::sync::Arc;
+use tokio::io::AsyncWrite;
+use tokio::sync::OnceCell;
+
+/// For volumes which link the original PDF (or one PDF per chapter) on their landing page.
+/// Linked supplementary material (solutions, worksheets, ...) is not part of the volume, see `Attachment`.
+/// Preferred over rendering the viewer, because the original keeps its text and vector graphics.
+///
+/// `OutputProfile`s are ignored, the files are kept exactly as they are served.
+#[derive(Debug)]
+pub struct NativePdfScraper {
+    /// Title and URL of every file, in the order they are linked.
+    files: Vec<(String, Url)>,
+    /// Page count of every file, the files themselves are downloaded again whenever they are needed.
+    page_counts: OnceCell<Vec<u16>>,
+
+    client: Arc<HttpClient>,
+}
+
+impl NativePdfScraper {
+    fn get_files(resp: &BufferedResponse) -> Vec<(String, Url)> {
+        let doc = Html::parse_document(resp.text());
+        let selector = Selector::parse(r#"a[href$=".pdf" i]"#).unwrap();
+
+        let mut files: Vec<(String, Url)> = Vec::new();
+        for link in doc.root_element().select(&selector) {
+            let Ok(url) = resp.url().join(link.attr("href").unwrap()) else {
+                continue;
+            };
+
+            // the same file is often linked by its thumbnail and its title
+            if files.iter().any(|(_, file)| *file == url) {
+                continue;
+            }
+
+            let title = link.text().collect::<String>().trim().to_string();
+            if Attachment::is_material_title(&title) {
+                continue;
+            }
+
+            let title = match title.is_empty() {
+                true => url
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .unwrap_or_default()
+                    .to_string(),
+                false => title,
+            };
+
+            files.push((title, url));
+        }
+
+        files
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(url = %url)))]
+    async fn fetch_file(&self, url: &Url) -> Result<Document, ScraperError> {
+        let resp = self.client.send(self.client.get(url.clone())).await?;
+        resp.error_for_status_ref()?;
+        Ok(Document::load_mem(resp.bytes())?)
+    }
+
+    /// Downloads the files one at a time, only their page counts are kept.
+    async fn get_page_counts(&self) -> Result<&Vec<u16>, ScraperError> {
+        self.page_counts
+            .get_or_try_init(|| async {
+                let mut page_counts = Vec::with_capacity(self.files.len());
+                for (_, url) in &self.files {
+                    page_counts.push(self.fetch_file(url).await?.get_pages().len() as u16);
+                }
+                Ok(page_counts)
+            })
+            .await
+    }
+
+    /// Downloads only the file containing the (one-based) `page` and extracts the page from it.
+    async fn get_page(&self, page: u16) -> Result<Document, ScraperError> {
+        let mut remaining = page;
+
+        for ((_, url), page_count) in self.files.iter().zip(self.get_page_counts().await?) {
+            if remaining > *page_count {
+                remaining -= page_count;
+                continue;
+            }
+
+            let document = self.fetch_file(url).await?;
+            let page_id = document.get_pages()[&u32::from(remaining)];
+            return Ok(extract_page(&document, page_id));
+        }
+
+        panic!("tried downloading invalid page: {page}")
+    }
+
+    async fn get_raw_page(&self, page: u16) -> Result<Vec<u8>, ScraperError> {
+        let mut buf = Vec::new();
+        self.get_page(page).await?.save_to(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[async_trait]
+impl Scraper for NativePdfScraper {
+    async fn fetch_page_raw_pdf(
+        &self,
+        page: u16,
+        _profile: &OutputProfile,
+    ) -> Result<Vec<u8>, ScraperError> {
+        self.get_raw_page(page).await
+    }
+
+    async fn fetch_page_source(&self, page: u16) -> Result<Vec<u8>, ScraperError> {
+        self.get_raw_page(page).await
+    }
+
+    async fn fetch_page_pdf(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<Document, ScraperError> {
+        let mut document = self.get_page(page).await?;
+        profile.apply_paper_size(&mut document);
+        Ok(document)
+    }
+
+    /// Copies the files one after another, every file gets a bookmark with its title.
+    /// Only one file is kept in memory at a time.
+    async fn write_book(
+        &self,
+        writer: &mut PdfWriter<&mut (dyn AsyncWrite + Unpin + Send)>,
+        profile: &OutputProfile,
+        title: Option<String>,
+    ) -> Result<VolumeManifest, ScraperError> {
+        let mut parent = None;
+        let mut pages = Vec::new();
+        let mut page_counts = Vec::with_capacity(self.files.len());
+
+        for (file_title, url) in &self.files {
+            let mut document = self.fetch_file(url).await?;
+            profile.apply_paper_size(&mut document);
+
+            let page_count = document.get_pages().len() as u16;
+            page_counts.push(page_count);
+            for _ in 0..page_count {
+                let page = pages.len() as u16 + 1;
+                pages.push(PageManifest::without_source(page, Some(url.clone())));
+            }
+
+            // links within a file jump to pages of the same file
+            writer.start_section(1..=page_count);
+            let index = writer.add_document(document).await?;
+
+            if let (None, Some(title)) = (parent, &title) {
+                parent = Some(writer.add_bookmark(title.clone(), index, None));
+            }
+            writer.add_bookmark(file_title.clone(), index, parent);
+        }
+
+        let page_count = page_counts.iter().sum();
+        // the files were just downloaded, no need to download them again for the page count
+        let _ = self.page_counts.set(page_counts);
+        Ok(VolumeManifest::new(page_count, pages))
+    }
+}
+
+#[async_trait]
+impl BaseScraper for NativePdfScraper {
+    fn new_scraper(
+        resp: Arc<BufferedResponse>,
+        client: Arc<HttpClient>,
+    ) -> Result<Box<dyn Scraper>, ScraperError>
+    where
+        Self: Sized,
+    {
+        Ok(Box::new(NativePdfScraper {
+            files: Self::get_files(&resp),
+            page_counts: OnceCell::new(),
+
+            client,
+        }))
+    }
+
+    /// Also viewer pages that offer a PDF download, the original is preferred over rendering the viewer.
+    fn probe(resp: &BufferedResponse) -> bool
+    where
+        Self: Sized,
+    {
+        !Self::get_files(resp).is_empty()
+    }
+
+    async fn fetch_page_count(&self) -> Result<u16, ScraperError> {
+        Ok(self.get_page_counts().await?.iter().sum())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffered_response::tests::response;
+
+    #[tokio::test]
+    async fn lists_linked_files_once() {
+        let resp = response(
+            "text/html",
+            r#"<a href="files/band1.pdf"><img src="thumb.jpg"></a>
+            <a href="files/band1.pdf">Band 1</a>
+            <a href="files/loesungen.PDF">Lösungen</a>
+            <a href="files/band2.PDF">Band 2</a>"#,
+        )
+        .await;
+
+        let files: Vec<_> = NativePdfScraper::get_files(&resp)
+            .into_iter()
+            .map(|(title, url)| (title, url.path().to_string()))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("band1.pdf".to_string(), "/files/band1.pdf".to_string()),
+                ("Band 2".to_string(), "/files/band2.PDF".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn recognizes_viewers_with_download_link() {
+        let resp = response(
+            "text/html",
+            r#"<script>IDRViewer.makeNavBar(120,'.jpg',</script><a href="original.pdf">Download</a>"#,
+        )
+        .await;
+        assert!(NativePdfScraper::probe(&resp));
+
+        let resp = response("text/html", r#"<a href="original.pdf">Buch</a>"#).await;
+        assert!(NativePdfScraper::probe(&resp));
+
+        let resp = response("text/html", r#"<a href="loesungen.pdf">Lösungen</a>"#).await;
+        assert!(!NativePdfScraper::probe(&resp));
+    }
+
+    #[tokio::test]
+    async fn missing_file_is_an_error() {
+        let client = HttpClient::new(reqwest::Client::new())
+            .with_transport(Arc::new(crate::http::replay::Replay::new()));
+        let scraper = NativePdfScraper {
+            files: vec![(
+                "Band 1".into(),
+                Url::parse("https://a.hpthek.at/band1.pdf").unwrap(),
+            )],
+            page_counts: OnceCell::new(),
+            client: Arc::new(client),
+        };
+
+        let error = scraper.fetch_page_count().await.unwrap_err();
+        assert!(matches!(error, ScraperError::Request(_)), "{error:?}");
+    }
+}
@@ -1,36 +1,258 @@
+use crate::best_effort::{self, DownloadReport, RetryPolicy};
 use crate::error::ScraperError;
+use crate::manifest::{Manifest, PageManifest, VolumeManifest};
+use crate::output_profile::OutputProfile;
+use crate::page::Page;
+use crate::page_size::PageSize;
 use crate::scraper::base_scraper::BaseScraper;
-use crate::scraper::util::merge_pdf;
+use crate::scraper::outline::BookOutline;
+use crate::scraper::pdf_writer::PdfWriter;
+use crate::scraper::util;
+use crate::trace_event;
 use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
 use lopdf::Document;
 use std::fmt::Debug;
 use std::io::Cursor;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncWrite, BufWriter};
 
 #[async_trait]
 pub trait Scraper: BaseScraper + Sync + Send + Debug {
-    async fn fetch_page_raw_pdf(&self, page: u16) -> Result<Vec<u8>, reqwest::Error>; // pdf bytes
+    async fn fetch_page_raw_pdf(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<Vec<u8>, ScraperError>; // pdf bytes
 
-    async fn fetch_page_pdf(&self, page: u16) -> Result<Document, ScraperError> {
-        Ok(Document::load_from(Cursor::new(
-            self.fetch_page_raw_pdf(page).await?,
-        ))?)
+    /// The page as it is served, before any conversion.
+    /// Cheaper than converting the page, so it is used to detect changed pages.
+    async fn fetch_page_source(&self, page: u16) -> Result<Vec<u8>, ScraperError>;
+
+    /// Converts a page and scales it to the paper size of `profile`, if it has one.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(scraper = std::any::type_name::<Self>(), page = page)))]
+    async fn fetch_page_pdf(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<Document, ScraperError> {
+        let mut document =
+            Document::load_from(Cursor::new(self.fetch_page_raw_pdf(page, profile).await?))?;
+        profile.apply_paper_size(&mut document);
+
+        Ok(document)
+    }
+
+    /// The (one-based) page with everything known about it, see `Page`.
+    /// The default implementation only knows the converted page, its label and its URL.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(scraper = std::any::type_name::<Self>(), page = page)))]
+    async fn fetch_page(&self, page: u16, profile: &OutputProfile) -> Result<Page, ScraperError> {
+        let pdf = self.fetch_page_pdf(page, profile).await?;
+
+        Ok(
+            Page::new(page, self.get_page_label(page), pdf)
+                .with_source_url(self.get_page_url(page)),
+        )
+    }
+
+    /// Physical size of a page, in the PDF it is the size of its `MediaBox`.
+    /// The default implementation converts the page, scrapers should override it if the size is known beforehand.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(scraper = std::any::type_name::<Self>(), page = page)))]
+    async fn fetch_page_size(&self, page: u16) -> Result<PageSize, ScraperError> {
+        let document = self.fetch_page_pdf(page, &OutputProfile::ARCHIVE).await?;
+        let page_id = *document
+            .get_pages()
+            .values()
+            .next()
+            .ok_or(lopdf::Error::PageNumberNotFound(1))?;
+
+        Ok(util::page_size(&document, page_id).unwrap_or(PageSize::A4))
+    }
+
+    /// A lower quality version of a page, which replaces it in best-effort downloads
+    /// if it can't be converted (see `Scraper::write_pages_best_effort`).
+    /// `None` if the scraper has no such version.
+    async fn fetch_fallback_page(
+        &self,
+        _page: u16,
+        _profile: &OutputProfile,
+    ) -> Result<Option<Document>, ScraperError> {
+        Ok(None)
     }
 
+    /// Downloads the book with the images exactly as they are served.
     async fn download_book(&self) -> Result<Document, ScraperError> {
+        self.download_book_with_profile(&OutputProfile::default())
+            .await
+    }
+
+    /// Keeps the whole book in memory.
+    /// Prefer `download_book_to` or `download_book_to_file` for large books.
+    async fn download_book_with_profile(
+        &self,
+        profile: &OutputProfile,
+    ) -> Result<Document, ScraperError> {
+        let mut buf = Vec::new();
+        self.download_book_to(&mut buf, profile).await?;
+
+        Ok(Document::load_mem(&buf)?)
+    }
+
+    /// Writes every page to `out` as soon as it is converted.
+    async fn download_book_to(
+        &self,
+        out: &mut (dyn AsyncWrite + Unpin + Send),
+        profile: &OutputProfile,
+    ) -> Result<VolumeManifest, ScraperError> {
+        let mut writer = PdfWriter::new(out).await?;
+        let manifest = self.write_book(&mut writer, profile, None).await?;
+
+        writer.finish().await?;
+        Ok(manifest)
+    }
+
+    /// Appends every page to `writer`, nothing if the volume has no pages.
+    /// If a `title` is given, the chapter and page bookmarks are nested below a bookmark with that title.
+    async fn write_book(
+        &self,
+        writer: &mut PdfWriter<&mut (dyn AsyncWrite + Unpin + Send)>,
+        profile: &OutputProfile,
+        title: Option<String>,
+    ) -> Result<VolumeManifest, ScraperError> {
         let page_count = self.fetch_page_count().await?;
-        assert!(page_count >= 1, "no pages to download");
 
-        let mut merge_doc = None;
+        let pages = self
+            .write_pages(writer, profile, title, 1..=page_count, 1, &|_| {})
+            .await?;
+        Ok(VolumeManifest::new(page_count, pages))
+    }
+
+    /// Like `write_book`, but only appends the (one-based) `pages`.
+    /// Up to `concurrency` pages are downloaded and converted at once, they are still written in order.
+    /// `progress` is called with the number of every written page.
+    ///
+    /// Returns the manifest of every written page.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(scraper = std::any::type_name::<Self>(), pages = ?pages, concurrency = concurrency)))]
+    async fn write_pages(
+        &self,
+        writer: &mut PdfWriter<&mut (dyn AsyncWrite + Unpin + Send)>,
+        profile: &OutputProfile,
+        title: Option<String>,
+        pages: RangeInclusive<u16>,
+        concurrency: usize,
+        progress: &(dyn Fn(u16) + Send + Sync),
+    ) -> Result<Vec<PageManifest>, ScraperError> {
+        let mut outline = BookOutline::new(title, &self.get_chapters());
+        let mut manifests = Vec::new();
+        writer.start_section(pages.clone());
+
+        let mut fetched = stream::iter(pages)
+            .map(|page| async move { (page, self.fetch_page(page, profile).await) })
+            .buffered(concurrency.max(1));
+
+        while let Some((page, fetched)) = fetched.next().await {
+            let fetched = fetched?;
+            manifests.push(PageManifest::new(&fetched, None));
+            let index = writer.add_document(fetched.into_pdf()).await?;
+            outline.add_page(writer, page, index);
+            trace_event!(DEBUG, page, index, "wrote page");
+            progress(page);
+        }
+
+        Ok(manifests)
+    }
+
+    /// Like `download_book_with_profile`, but pages that can't be downloaded or converted
+    /// are replaced instead of failing the download, see `write_pages_best_effort`.
+    /// The report lists every replaced page.
+    async fn download_book_best_effort(
+        &self,
+        profile: &OutputProfile,
+        policy: &RetryPolicy,
+    ) -> Result<(Document, DownloadReport), ScraperError> {
+        let mut buf = Vec::new();
+        let out: &mut (dyn AsyncWrite + Unpin + Send) = &mut buf;
+        let mut writer = PdfWriter::new(out).await?;
+        let manifest = self
+            .write_book_best_effort(&mut writer, profile, None, policy)
+            .await?;
+        writer.finish().await?;
+
+        Ok((
+            Document::load_mem(&buf)?,
+            DownloadReport::for_volumes(&[manifest]),
+        ))
+    }
+
+    /// Like `write_book`, but replaces failing pages like `write_pages_best_effort`.
+    async fn write_book_best_effort(
+        &self,
+        writer: &mut PdfWriter<&mut (dyn AsyncWrite + Unpin + Send)>,
+        profile: &OutputProfile,
+        title: Option<String>,
+        policy: &RetryPolicy,
+    ) -> Result<VolumeManifest, ScraperError> {
+        let page_count = self.fetch_page_count().await?;
+
+        let pages = self
+            .write_pages_best_effort(writer, profile, title, 1..=page_count, 1, policy, &|_| {})
+            .await?;
+        Ok(VolumeManifest::new(page_count, pages))
+    }
 
-        for i in 1..=page_count {
-            let page = self.fetch_page_pdf(i).await?;
+    /// Like `write_pages`, but failing pages are replaced by `fetch_fallback_page` or a placeholder page.
+    /// Pages failing with transient request errors (e.g. timeouts or `503`) are retried according to `policy` first.
+    /// Only fails if the pages can't be written.
+    ///
+    /// Returns the manifest of every written page, replaced pages have a `PageManifest::failure`.
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(scraper = std::any::type_name::<Self>(), pages = ?pages, concurrency = concurrency)))]
+    async fn write_pages_best_effort(
+        &self,
+        writer: &mut PdfWriter<&mut (dyn AsyncWrite + Unpin + Send)>,
+        profile: &OutputProfile,
+        title: Option<String>,
+        pages: RangeInclusive<u16>,
+        concurrency: usize,
+        policy: &RetryPolicy,
+        progress: &(dyn Fn(u16) + Send + Sync),
+    ) -> Result<Vec<PageManifest>, ScraperError> {
+        let mut outline = BookOutline::new(title, &self.get_chapters());
+        let mut manifests = Vec::new();
+        writer.start_section(pages.clone());
 
-            match merge_doc {
-                None => merge_doc = Some(page),
-                Some(doc) => merge_doc = Some(merge_pdf(doc, page)?),
-            }
+        let mut fetched = stream::iter(pages)
+            .map(|page| async move {
+                let (fetched, failure) = best_effort::fetch_page(self, page, profile, policy).await;
+                (page, fetched, failure)
+            })
+            .buffered(concurrency.max(1));
+
+        while let Some((page, fetched, failure)) = fetched.next().await {
+            manifests.push(PageManifest::new(&fetched, failure));
+            let index = writer.add_document(fetched.into_pdf()).await?;
+            outline.add_page(writer, page, index);
+            trace_event!(DEBUG, page, index, "wrote page");
+            progress(page);
         }
 
-        Ok(merge_doc.unwrap())
+        Ok(manifests)
+    }
+
+    /// Also saves a `Manifest` next to the file.
+    async fn download_book_to_file(
+        &self,
+        path: &Path,
+        profile: &OutputProfile,
+    ) -> Result<Manifest, ScraperError> {
+        let mut file = BufWriter::new(File::create(path).await?);
+        let volume = self.download_book_to(&mut file, profile).await?;
+        drop(file);
+
+        let manifest = Manifest::for_file(path, vec![volume]).await?;
+        manifest.save(path).await?;
+        Ok(manifest)
     }
 }
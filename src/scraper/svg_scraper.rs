@@ -1,89 +1,250 @@
 use crate::buffered_response::BufferedResponse;
+use crate::error::ScraperError;
+use crate::output_profile::OutputProfile;
+use crate::page::{Page, PageResource};
+use crate::page_size::PageSize;
 use crate::scraper::base_scraper::BaseScraper;
+use crate::scraper::links::svg_to_pdf_with_links;
 use crate::scraper::scraper_trait::Scraper;
+use crate::scraper::util::image_to_pdf;
+use crate::{regex, trace_event};
 use async_trait::async_trait;
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
-use regex::Regex;
-use reqwest::RequestBuilder;
-use std::collections::HashMap;
+use image::ImageReader;
+use lopdf::Document;
 use std::fmt::Debug;
+use std::io::Cursor;
 
 #[async_trait]
 pub trait SvgScraper: BaseScraper + Sync + Send + Debug {
     /// get an unmodified svg directly from the page
     async fn get_page_raw_svg(&self, page: u16) -> Result<String, reqwest::Error>;
-    fn get_image_request(&self, relative_url: &str) -> RequestBuilder;
+    /// Downloads an image referenced by the svg of a page.
+    async fn get_image(&self, relative_url: &str) -> Result<BufferedResponse, reqwest::Error>;
 
-    async fn get_page_svg(&self, page: u16) -> Result<String, reqwest::Error> {
+    /// A bitmap of the whole page (e.g. a thumbnail of the viewer),
+    /// which replaces the page in best-effort downloads if its svg can't be converted.
+    async fn get_page_fallback_image(
+        &self,
+        _page: u16,
+    ) -> Result<Option<BufferedResponse>, reqwest::Error> {
+        Ok(None)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(page = page)))]
+    async fn get_page_svg(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<String, ScraperError> {
         let raw_svg = self.get_page_raw_svg(page).await?;
-        let mut svg = raw_svg.clone();
+        let images = fetch_images(self, page, &raw_svg).await?;
 
-        let mut images = HashMap::new();
+        embed_images(&raw_svg, &images, profile)
+    }
 
-        let url_regex = Regex::new(&format!("xlink:href=\"({}/.+?)\"/>", page)).unwrap();
-        // TODO add rayon iter
-        for capture in url_regex.captures_iter(&raw_svg) {
-            let url = capture.get(1).unwrap().as_str();
+    async fn fetch_page_pdf(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<Vec<u8>, ScraperError> {
+        let svg = self.get_page_svg(page, profile).await?;
+        svg_to_pdf_with_links(&svg)
+    }
+}
 
-            // skip already downloaded images
-            if !images.contains_key(url) {
-                let resp = BufferedResponse::new(self.get_image_request(url).send().await?).await?;
-                images.insert(url, resp);
-            };
-        }
+#[async_trait]
+impl<T> Scraper for T
+where
+    T: SvgScraper,
+{
+    async fn fetch_page_raw_pdf(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<Vec<u8>, ScraperError> {
+        SvgScraper::fetch_page_pdf(self, page, profile).await
+    }
 
-        for (url, resp) in images {
-            let content_type = {
-                let content_type_header = resp.headers().get("Content-Type").unwrap_or_else(|| {
-                    panic!(
-                        "No Content-Type specified for downloaded content: {}",
-                        resp.url().as_str()
-                    )
-                });
-
-                content_type_header
-                    .to_str()
-                    .unwrap_or_else(|_| {
-                        panic!(
-                            "Content-Type is not a valid string: {:?}",
-                            content_type_header
-                        )
-                    })
-                    .to_owned()
-            };
-
-            svg = svg.replace(
-                url,
-                &format!(
-                    "data:{};base64,{}",
-                    content_type,
-                    &BASE64_STANDARD.encode(resp.bytes())
-                ),
-            );
+    async fn fetch_page_source(&self, page: u16) -> Result<Vec<u8>, ScraperError> {
+        Ok(self.get_page_raw_svg(page).await?.into_bytes())
+    }
+
+    async fn fetch_page(&self, page: u16, profile: &OutputProfile) -> Result<Page, ScraperError> {
+        let raw_svg = self.get_page_raw_svg(page).await?;
+        let images = fetch_images(self, page, &raw_svg).await?;
+
+        let svg = embed_images(&raw_svg, &images, profile)?;
+        let mut pdf = Document::load_from(Cursor::new(svg_to_pdf_with_links(&svg)?))?;
+        profile.apply_paper_size(&mut pdf);
+
+        let resources = images
+            .into_iter()
+            .map(|image| {
+                PageResource::new(
+                    image.resp.url().clone(),
+                    content_type(&image.resp),
+                    image.resp.body(),
+                )
+            })
+            .collect();
+
+        Ok(Page::new(page, self.get_page_label(page), pdf)
+            .with_source_url(self.get_page_url(page))
+            .with_resources(resources)
+            .with_raw_svg(raw_svg))
+    }
+
+    async fn fetch_page_size(&self, page: u16) -> Result<PageSize, ScraperError> {
+        let svg = self.get_page_raw_svg(page).await?;
+
+        if let Some(size) = PageSize::from_svg(&svg) {
+            return Ok(size);
         }
 
-        Ok(svg)
+        // usvg falls back to a default size, the images aren't needed for that
+        let size = svg2pdf::usvg::Tree::from_str(&svg, &Default::default())?.size();
+        Ok(PageSize::new(size.width(), size.height()))
     }
 
-    async fn fetch_page_pdf(&self, page: u16) -> Result<Vec<u8>, reqwest::Error> {
-        let svg = self.get_page_svg(page).await?;
-        let tree =
-            svg2pdf::usvg::Tree::from_str(&svg, &Default::default()).expect("malformed svg found");
+    async fn fetch_fallback_page(
+        &self,
+        page: u16,
+        profile: &OutputProfile,
+    ) -> Result<Option<Document>, ScraperError> {
+        let Some(resp) = self.get_page_fallback_image(page).await? else {
+            return Ok(None);
+        };
+
+        // the image is smaller than the page, so its size is only used if the svg doesn't specify one
+        let size = match self.get_page_raw_svg(page).await {
+            Ok(svg) => PageSize::from_svg(&svg),
+            Err(_) => None,
+        };
+        let (width, height) = match size {
+            Some(size) => (size.width(), size.height()),
+            None => {
+                let Some((width, height)) = ImageReader::new(Cursor::new(resp.bytes()))
+                    .with_guessed_format()
+                    .ok()
+                    .and_then(|reader| reader.into_dimensions().ok())
+                else {
+                    return Ok(None);
+                };
+                let a4 = PageSize::A4;
+                (a4.width(), a4.width() * height as f32 / width as f32)
+            }
+        };
+
+        let content_type = content_type(&resp).unwrap_or_else(|| "image/jpeg".to_string());
+        let (content_type, data) =
+            profile.process_image(&content_type, resp.bytes(), Some((width, height)));
+
+        let mut pdf = Document::load_from(Cursor::new(image_to_pdf(
+            &content_type,
+            &data,
+            width,
+            height,
+        )?))?;
+        profile.apply_paper_size(&mut pdf);
 
-        Ok(
-            svg2pdf::to_pdf(&tree, Default::default(), Default::default())
-                .expect("failed to convert svg to pdf"),
-        )
+        Ok(Some(pdf))
     }
 }
 
-#[async_trait]
-impl<T> Scraper for T
-where
-    T: SvgScraper,
-{
-    async fn fetch_page_raw_pdf(&self, page: u16) -> Result<Vec<u8>, reqwest::Error> {
-        SvgScraper::fetch_page_pdf(self, page).await
+/// An image referenced by the svg of a page.
+struct PageImage {
+    /// As referenced by the svg.
+    href: String,
+    resp: BufferedResponse,
+    /// The largest size the image is drawn at.
+    display_size: Option<(f32, f32)>,
+}
+
+/// Downloads every image referenced by `raw_svg` once, in the order they are first referenced.
+async fn fetch_images<S: SvgScraper + ?Sized>(
+    scraper: &S,
+    page: u16,
+    raw_svg: &str,
+) -> Result<Vec<PageImage>, reqwest::Error> {
+    let mut images: Vec<PageImage> = Vec::new();
+
+    let prefix = format!("{page}/");
+    // TODO add rayon iter
+    for capture in regex!(r#"(<image [^>]*?)?xlink:href="(\d+/.+?)"/>"#).captures_iter(raw_svg) {
+        let Some(href) = capture
+            .get(2)
+            .map(|m| m.as_str())
+            .filter(|href| href.starts_with(&prefix))
+        else {
+            continue;
+        };
+        let display_size = capture.get(1).and_then(|m| image_size(m.as_str()));
+
+        match images.iter_mut().find(|image| image.href == href) {
+            // an image can be drawn multiple times, its largest size decides the needed resolution
+            Some(image) => {
+                if let Some((width, height)) = display_size {
+                    let size = image.display_size.get_or_insert((width, height));
+                    *size = (size.0.max(width), size.1.max(height));
+                }
+            }
+            None => images.push(PageImage {
+                href: href.to_string(),
+                resp: scraper.get_image(href).await?,
+                display_size,
+            }),
+        }
     }
+
+    trace_event!(DEBUG, images = images.len(), "downloaded page images");
+    Ok(images)
+}
+
+/// Replaces the references to `images` with data URLs of the images processed by `profile`.
+fn embed_images(
+    raw_svg: &str,
+    images: &[PageImage],
+    profile: &OutputProfile,
+) -> Result<String, ScraperError> {
+    let mut svg = raw_svg.to_string();
+
+    for image in images {
+        let content_type = content_type(&image.resp)
+            .ok_or_else(|| ScraperError::MissingContentType(image.resp.url().clone()))?;
+
+        let (content_type, data) =
+            profile.process_image(&content_type, image.resp.bytes(), image.display_size);
+
+        svg = svg.replace(
+            &image.href,
+            &format!(
+                "data:{};base64,{}",
+                content_type,
+                &BASE64_STANDARD.encode(data)
+            ),
+        );
+    }
+
+    Ok(svg)
+}
+
+fn content_type(resp: &BufferedResponse) -> Option<String> {
+    resp.headers()
+        .get("Content-Type")
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// Reads the size of an `<image>` element from its opening tag.
+fn image_size(tag: &str) -> Option<(f32, f32)> {
+    let width = regex!(r#"\swidth="([\d.]+)""#).captures(tag)?[1]
+        .parse()
+        .ok()?;
+    let height = regex!(r#"\sheight="([\d.]+)""#).captures(tag)?[1]
+        .parse()
+        .ok()?;
+
+    Some((width, height))
 }
@@ -1,5 +1,147 @@
-use lopdf::{Bookmark, Document, Object, ObjectId};
-use std::collections::BTreeMap;
+use crate::error::ScraperError;
+use crate::page_size::PageSize;
+use base64::prelude::BASE64_STANDARD;
+use base64::Engine;
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Bookmark, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+/// Converts a page with all of its resources inlined.
+pub(crate) fn svg_to_pdf(svg: &str) -> Result<Vec<u8>, ScraperError> {
+    let tree = svg2pdf::usvg::Tree::from_str(svg, &Default::default())?;
+
+    svg2pdf::to_pdf(&tree, Default::default(), Default::default())
+        .map_err(ScraperError::SvgConversion)
+}
+
+/// A page of `width` x `height` points filled by an image.
+pub(crate) fn image_to_pdf(
+    content_type: &str,
+    data: &[u8],
+    width: f32,
+    height: f32,
+) -> Result<Vec<u8>, ScraperError> {
+    let svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="{width}" height="{height}" viewBox="0 0 {width} {height}"><image width="{width}" height="{height}" preserveAspectRatio="none" xlink:href="data:{content_type};base64,{}"/></svg>"#,
+        BASE64_STANDARD.encode(data)
+    );
+
+    svg_to_pdf(&svg)
+}
+
+/// A framed page of `size` showing `lines` of text, stands in for pages that couldn't be downloaded.
+/// Lines are wrapped to the page width, characters outside of ASCII are replaced.
+pub(crate) fn placeholder_page(size: PageSize, lines: &[String]) -> Document {
+    const MARGIN: f32 = 36.0;
+    let font_size = (size.width() / 40.0).clamp(6.0, 14.0);
+    // Helvetica is about half as wide as it is high
+    let max_chars = ((size.width() - 2.0 * MARGIN) / (font_size * 0.55)).max(10.0) as usize;
+
+    let mut operations = vec![
+        Operation::new("q", vec![]),
+        Operation::new("G", vec![0.6.into()]),
+        Operation::new("w", vec![2.into()]),
+        Operation::new(
+            "re",
+            vec![
+                (MARGIN / 2.0).into(),
+                (MARGIN / 2.0).into(),
+                (size.width() - MARGIN).into(),
+                (size.height() - MARGIN).into(),
+            ],
+        ),
+        Operation::new("S", vec![]),
+        Operation::new("Q", vec![]),
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec!["F1".into(), font_size.into()]),
+        Operation::new("TL", vec![(font_size * 1.4).into()]),
+        Operation::new(
+            "Td",
+            vec![MARGIN.into(), (size.height() - MARGIN - font_size).into()],
+        ),
+    ];
+
+    for line in lines.iter().flat_map(|line| wrap_line(line, max_chars)) {
+        let line: String = line
+            .chars()
+            .map(|c| {
+                if c.is_ascii() && !c.is_control() {
+                    c
+                } else {
+                    '?'
+                }
+            })
+            .collect();
+        operations.push(Operation::new(
+            "Tj",
+            vec![Object::String(line.into_bytes(), StringFormat::Literal)],
+        ));
+        operations.push(Operation::new("T*", vec![]));
+    }
+    operations.push(Operation::new("ET", vec![]));
+
+    let mut document = Document::with_version("1.5");
+    let pages_id = document.new_object_id();
+    let font_id = document.add_object(dictionary! {
+        "Type" => "Font",
+        "Subtype" => "Type1",
+        "BaseFont" => "Helvetica",
+    });
+    let content_id = document.add_object(Stream::new(
+        Dictionary::new(),
+        Content { operations }.encode().unwrap(),
+    ));
+    let page_id = document.add_object(dictionary! {
+        "Type" => "Page",
+        "Parent" => pages_id,
+        "MediaBox" => vec![0.into(), 0.into(), size.width().into(), size.height().into()],
+        "Contents" => content_id,
+        "Resources" => dictionary! {
+            "Font" => dictionary! { "F1" => font_id },
+        },
+    });
+    document.objects.insert(
+        pages_id,
+        Object::Dictionary(dictionary! {
+            "Type" => "Pages",
+            "Kids" => vec![page_id.into()],
+            "Count" => 1,
+        }),
+    );
+    let catalog_id = document.add_object(dictionary! {
+        "Type" => "Catalog",
+        "Pages" => pages_id,
+    });
+    document.trailer.set("Root", catalog_id);
+
+    document
+}
+
+/// Splits a line at spaces (or anywhere, for longer words) into lines of at most `max_chars`.
+fn wrap_line(line: &str, max_chars: usize) -> Vec<String> {
+    let mut lines = vec![String::new()];
+
+    for word in line.split(' ') {
+        let chars: Vec<char> = word.chars().collect();
+        for chunk in chars.chunks(max_chars) {
+            let chunk: String = chunk.iter().collect();
+            let current = lines.last_mut().unwrap();
+
+            if current.is_empty() {
+                *current = chunk;
+            } else if current.chars().count() + 1 + chunk.chars().count() <= max_chars {
+                current.push(' ');
+                current.push_str(&chunk);
+            } else {
+                lines.push(chunk);
+            }
+        }
+    }
+
+    lines
+}
 
 // snippet from https://github.com/J-F-Liu/lopdf example code (in Readme)
 pub fn merge_pdf(parent: Document, child: Document) -> Result<Document, lopdf::Error> {
@@ -167,7 +309,371 @@ pub fn merge_pdf(parent: Document, child: Document) -> Result<Document, lopdf::E
     }
 
     document.compress();
+    dedup_objects(&mut document);
     document.prune_objects();
 
     Ok(document)
 }
+
+/// Shares identical images and fonts across pages.
+/// Every page is converted on its own, so resources used on many pages (logos, backgrounds, fonts)
+/// end up in the document once per page. Duplicates are redirected to a single object and
+/// left unreferenced, so `prune_objects` has to be called afterwards.
+///
+/// Streams have to be compressed the same way for them to be detected as duplicates.
+pub(crate) fn dedup_objects(document: &mut Document) {
+    // Objects may only become identical once the objects they reference have been merged
+    // (e.g. an image and its SMask, a font and its descriptor), so repeat until nothing changes.
+    loop {
+        let mut candidates: HashMap<u64, Vec<ObjectId>> = HashMap::new();
+        let mut replacements: HashMap<ObjectId, ObjectId> = HashMap::new();
+
+        for (object_id, object) in document.objects.iter() {
+            if !is_shareable(object) {
+                continue;
+            }
+
+            let bucket = candidates.entry(hash_object(object)).or_default();
+            match bucket
+                .iter()
+                .find(|id| objects_equal(&document.objects[id], object))
+            {
+                Some(original) => {
+                    replacements.insert(*object_id, *original);
+                }
+                None => bucket.push(*object_id),
+            }
+        }
+
+        if replacements.is_empty() {
+            return;
+        }
+
+        for object in document.objects.values_mut() {
+            replace_references(object, &replacements);
+        }
+        for (_, object) in document.trailer.iter_mut() {
+            replace_references(object, &replacements);
+        }
+        for object_id in replacements.keys() {
+            document.objects.remove(object_id);
+        }
+    }
+}
+
+/// Only resources can be shared. Pages, the page tree and outlines have to stay unique.
+pub(crate) fn is_shareable(object: &Object) -> bool {
+    match object {
+        Object::Stream(_) => true,
+        Object::Dictionary(dict) => matches!(
+            dict.get(b"Type").and_then(Object::as_name),
+            Ok(b"Font" | b"FontDescriptor")
+        ),
+        _ => false,
+    }
+}
+
+fn objects_equal(a: &Object, b: &Object) -> bool {
+    match (a, b) {
+        // `Stream` equality also compares the position in the source file
+        (Object::Stream(a), Object::Stream(b)) => a.dict == b.dict && a.content == b.content,
+        _ => a == b,
+    }
+}
+
+fn hash_object(object: &Object) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_object_into(object, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_object_into(object: &Object, hasher: &mut DefaultHasher) {
+    std::mem::discriminant(object).hash(hasher);
+
+    match object {
+        Object::Null => {}
+        Object::Boolean(value) => value.hash(hasher),
+        Object::Integer(value) => value.hash(hasher),
+        Object::Real(value) => value.to_bits().hash(hasher),
+        Object::Name(name) => name.hash(hasher),
+        Object::String(text, _) => text.hash(hasher),
+        Object::Array(array) => array.iter().for_each(|item| hash_object_into(item, hasher)),
+        Object::Dictionary(dict) => hash_dictionary_into(dict, hasher),
+        Object::Stream(stream) => {
+            hash_dictionary_into(&stream.dict, hasher);
+            stream.content.hash(hasher);
+        }
+        Object::Reference(id) => id.hash(hasher),
+    }
+}
+
+fn hash_dictionary_into(dict: &Dictionary, hasher: &mut DefaultHasher) {
+    // dictionary equality doesn't depend on the order of the entries, so neither may the hash
+    let mut entries: Vec<_> = dict.iter().collect();
+    entries.sort_unstable_by_key(|(key, _)| *key);
+
+    for (key, value) in entries {
+        key.hash(hasher);
+        hash_object_into(value, hasher);
+    }
+}
+
+pub(crate) fn replace_references(object: &mut Object, replacements: &HashMap<ObjectId, ObjectId>) {
+    match object {
+        Object::Reference(id) => {
+            if let Some(replacement) = replacements.get(id) {
+                *id = *replacement;
+            }
+        }
+        Object::Array(array) => array
+            .iter_mut()
+            .for_each(|item| replace_references(item, replacements)),
+        Object::Dictionary(dict) => dict
+            .iter_mut()
+            .for_each(|(_, value)| replace_references(value, replacements)),
+        Object::Stream(stream) => stream
+            .dict
+            .iter_mut()
+            .for_each(|(_, value)| replace_references(value, replacements)),
+        _ => {}
+    }
+}
+
+/// Collects every object the pages depend on in ascending order.
+/// The parents of the pages aren't followed, so the old page tree (catalog, outline, ...) isn't included.
+pub(crate) fn reachable_objects(document: &Document, pages: &[ObjectId]) -> Vec<ObjectId> {
+    let mut reachable = HashSet::new();
+    let mut stack = pages.to_vec();
+
+    while let Some(object_id) = stack.pop() {
+        if !reachable.insert(object_id) {
+            continue;
+        }
+
+        if let Some(object) = document.objects.get(&object_id) {
+            collect_references(object, &mut stack);
+        }
+    }
+
+    let mut reachable: Vec<ObjectId> = reachable
+        .into_iter()
+        .filter(|id| document.objects.contains_key(id))
+        .collect();
+    reachable.sort_unstable();
+    reachable
+}
+
+fn collect_references(object: &Object, references: &mut Vec<ObjectId>) {
+    match object {
+        Object::Reference(id) => references.push(*id),
+        Object::Array(array) => array
+            .iter()
+            .for_each(|item| collect_references(item, references)),
+        Object::Dictionary(dict) => collect_dictionary_references(dict, references),
+        Object::Stream(stream) => collect_dictionary_references(&stream.dict, references),
+        _ => {}
+    }
+}
+
+fn collect_dictionary_references(dict: &Dictionary, references: &mut Vec<ObjectId>) {
+    for (key, value) in dict.iter() {
+        // the parent of a page is the page tree
+        if key != b"Parent" {
+            collect_references(value, references);
+        }
+    }
+}
+
+/// Copies a single page and everything it depends on into a new document.
+pub(crate) fn extract_page(document: &Document, page: ObjectId) -> Document {
+    let mut extracted = Document::with_version("1.5");
+
+    for object_id in reachable_objects(document, &[page]) {
+        extracted
+            .objects
+            .insert(object_id, document.objects[&object_id].clone());
+    }
+
+    extracted.max_id = document.max_id;
+    let pages_id = extracted.new_object_id();
+    let catalog_id = extracted.new_object_id();
+
+    if let Ok(Object::Dictionary(dict)) = extracted.get_object_mut(page) {
+        dict.set("Parent", pages_id);
+        for (key, value) in inherited_page_attributes(document, page) {
+            dict.set(key, value);
+        }
+    }
+
+    let mut pages = Dictionary::new();
+    pages.set("Type", "Pages");
+    pages.set("Count", 1);
+    pages.set("Kids", vec![Object::Reference(page)]);
+    extracted
+        .objects
+        .insert(pages_id, Object::Dictionary(pages));
+
+    let mut catalog = Dictionary::new();
+    catalog.set("Type", "Catalog");
+    catalog.set("Pages", pages_id);
+    extracted
+        .objects
+        .insert(catalog_id, Object::Dictionary(catalog));
+
+    extracted.trailer.set("Root", catalog_id);
+    extracted
+}
+
+/// Attributes a page can inherit from its ancestors in the page tree.
+const INHERITABLE_PAGE_ATTRIBUTES: [&[u8]; 4] = [b"MediaBox", b"CropBox", b"Resources", b"Rotate"];
+
+/// The attributes `page` inherits from the page tree, which it would lose when moved to another page tree.
+pub(crate) fn inherited_page_attributes(
+    document: &Document,
+    page: ObjectId,
+) -> Vec<(&'static [u8], Object)> {
+    let mut inherited: Vec<(&[u8], Object)> = Vec::new();
+    let Ok(dict) = document.get_dictionary(page) else {
+        return inherited;
+    };
+
+    let mut parent = dict.get(b"Parent").and_then(Object::as_reference);
+    // the depth limit protects against page trees with cycles
+    for _ in 0..32 {
+        let Some(parent_dict) = parent.ok().and_then(|id| document.get_dictionary(id).ok()) else {
+            break;
+        };
+
+        for key in INHERITABLE_PAGE_ATTRIBUTES {
+            if !dict.has(key) && !inherited.iter().any(|(k, _)| *k == key) {
+                if let Ok(value) = parent_dict.get(key) {
+                    inherited.push((key, value.clone()));
+                }
+            }
+        }
+        parent = parent_dict.get(b"Parent").and_then(Object::as_reference);
+    }
+
+    inherited
+}
+
+/// Size of the `MediaBox` of a page.
+pub(crate) fn page_size(document: &Document, page: ObjectId) -> Option<PageSize> {
+    let [x0, y0, x1, y1] = media_box(document, page)?;
+    Some(PageSize::new((x1 - x0).abs(), (y1 - y0).abs()))
+}
+
+fn media_box(document: &Document, page: ObjectId) -> Option<[f32; 4]> {
+    let mut dict = document.get_dictionary(page).ok()?;
+
+    // the depth limit protects against page trees with cycles
+    for _ in 0..32 {
+        if let Ok(media_box) = dict.get_deref(b"MediaBox", document) {
+            let values: Vec<f32> = media_box
+                .as_array()
+                .ok()?
+                .iter()
+                .map(|value| document.dereference(value).ok()?.1.as_float().ok())
+                .collect::<Option<_>>()?;
+            return values.try_into().ok();
+        }
+        dict = dict.get_deref(b"Parent", document).ok()?.as_dict().ok()?;
+    }
+
+    None
+}
+
+/// Scales the content of a page to fit `size` (keeping its aspect ratio), centers it and sets the `MediaBox` to `size`.
+pub(crate) fn fit_page(document: &mut Document, page: ObjectId, size: PageSize) {
+    let Some([x0, y0, x1, y1]) = media_box(document, page) else {
+        return;
+    };
+
+    let (width, height) = (x1 - x0, y1 - y0);
+    if width <= 0.0 || height <= 0.0 {
+        return;
+    }
+
+    let scale = (size.width() / width).min(size.height() / height);
+    let dx = (size.width() - width * scale) / 2.0 - x0 * scale;
+    let dy = (size.height() - height * scale) / 2.0 - y0 * scale;
+
+    let prefix = document.add_object(Stream::new(
+        Dictionary::new(),
+        format!("q {scale} 0 0 {scale} {dx} {dy} cm\n").into_bytes(),
+    ));
+    let suffix = document.add_object(Stream::new(Dictionary::new(), b"\nQ".to_vec()));
+
+    let mut annotations = Vec::new();
+    let Ok(dict) = document.get_dictionary_mut(page) else {
+        return;
+    };
+
+    let contents = match dict.get(b"Contents") {
+        Ok(Object::Array(contents)) => contents.clone(),
+        Ok(contents) => vec![contents.clone()],
+        Err(_) => Vec::new(),
+    };
+    dict.set(
+        "Contents",
+        [vec![prefix.into()], contents, vec![suffix.into()]].concat(),
+    );
+    dict.set(
+        "MediaBox",
+        vec![
+            0.into(),
+            0.into(),
+            size.width().into(),
+            size.height().into(),
+        ],
+    );
+    // the other boxes would cut off the scaled content
+    for key in [b"CropBox".as_slice(), b"BleedBox", b"TrimBox", b"ArtBox"] {
+        dict.remove(key);
+    }
+
+    match dict.get_mut(b"Annots") {
+        Ok(Object::Array(annots)) => annots.iter_mut().for_each(|annot| match annot {
+            Object::Dictionary(annot) => scale_rect(annot, scale, dx, dy),
+            Object::Reference(id) => annotations.push(*id),
+            _ => {}
+        }),
+        Ok(Object::Reference(id)) => annotations.push(*id),
+        _ => {}
+    }
+
+    while let Some(id) = annotations.pop() {
+        match document.get_object_mut(id) {
+            Ok(Object::Dictionary(annot)) => scale_rect(annot, scale, dx, dy),
+            Ok(Object::Array(annots)) => {
+                annotations.extend(annots.iter().filter_map(|annot| annot.as_reference().ok()))
+            }
+            _ => {}
+        }
+    }
+}
+
+fn scale_rect(annotation: &mut Dictionary, scale: f32, dx: f32, dy: f32) {
+    let Ok(rect) = annotation.get(b"Rect").and_then(Object::as_array) else {
+        return;
+    };
+    let Ok(values) = rect
+        .iter()
+        .map(Object::as_float)
+        .collect::<Result<Vec<f32>, _>>()
+    else {
+        return;
+    };
+
+    if let [x0, y0, x1, y1] = values[..] {
+        annotation.set(
+            "Rect",
+            vec![
+                (x0 * scale + dx).into(),
+                (y0 * scale + dy).into(),
+                (x1 * scale + dx).into(),
+                (y1 * scale + dy).into(),
+            ],
+        );
+    }
+}
@@ -0,0 +1,365 @@
+use crate::digi4school::book::Book;
+use crate::digi4school::session::Session;
+use crate::digi4school::volume::Volume;
+use crate::error::{DigiDownloadError, ScraperError};
+use crate::export::{export_volume, part_path, ExportFailure, ExportOptions};
+use crate::manifest::{Manifest, PageManifest, VolumeManifest};
+use crate::scraper::outline::BookOutline;
+use crate::scraper::pdf_writer::unresolve_destinations;
+use crate::scraper::scraper_trait::Scraper;
+use crate::scraper::util::extract_page;
+use crate::scraper::PdfWriter;
+use crate::trace_event;
+use crate::util::hex;
+use getset::Getters;
+use lopdf::Document;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::BufWriter;
+
+/// What was exported by previous syncs.
+/// Stored as JSON in the export directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    /// Keyed by book id.
+    books: BTreeMap<u16, BookState>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BookState {
+    title: String,
+    /// Keyed by volume URL.
+    volumes: BTreeMap<String, VolumeState>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct VolumeState {
+    /// Relative to the export directory.
+    path: PathBuf,
+    page_count: u16,
+    /// SHA-256 of the source of every page, see `Scraper::fetch_page_source`.
+    page_hashes: Vec<String>,
+}
+
+impl SyncState {
+    pub const FILE_NAME: &'static str = ".digidownload-sync.json";
+
+    /// Loads the state of a previous sync, or an empty state if `dir` was never synced.
+    pub async fn load(dir: &Path) -> Result<Self, std::io::Error> {
+        match tokio::fs::read(dir.join(Self::FILE_NAME)).await {
+            Ok(buf) => Ok(serde_json::from_slice(&buf)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn save(&self, dir: &Path) -> Result<(), std::io::Error> {
+        let path = dir.join(Self::FILE_NAME);
+        let part_path = part_path(&path);
+
+        tokio::fs::write(&part_path, serde_json::to_vec_pretty(self)?).await?;
+        tokio::fs::rename(part_path, path).await
+    }
+
+    fn volume(&self, book: &Book, volume: &Volume) -> Option<&VolumeState> {
+        self.books
+            .get(&book.id())?
+            .volumes
+            .get(volume.url().as_str())
+    }
+
+    fn set_volume(&mut self, book: &Book, volume: &Volume, state: VolumeState) {
+        self.books
+            .entry(book.id())
+            .or_insert_with(|| BookState {
+                title: book.title().clone(),
+                volumes: BTreeMap::new(),
+            })
+            .volumes
+            .insert(volume.url().to_string(), state);
+    }
+}
+
+/// Summary of `sync_library`.
+#[derive(Debug, Default, Getters)]
+#[getset(get = "pub")]
+pub struct SyncReport {
+    /// Volumes that weren't exported before.
+    added: Vec<PathBuf>,
+    /// Volumes of which some pages changed, with the (one-based) numbers of those pages.
+    updated: Vec<(PathBuf, Vec<u16>)>,
+    unchanged: Vec<PathBuf>,
+    failed: Vec<ExportFailure>,
+}
+
+impl SyncReport {
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty()
+    }
+}
+
+impl Display for SyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} added, {} updated, {} unchanged, {} failed",
+            self.added.len(),
+            self.updated.len(),
+            self.unchanged.len(),
+            self.failed.len()
+        )?;
+
+        for path in &self.added {
+            writeln!(f, "+ {}", path.display())?;
+        }
+        for (path, pages) in &self.updated {
+            writeln!(f, "~ {} (pages {:?})", path.display(), pages)?;
+        }
+        for failure in &self.failed {
+            match &failure.volume {
+                Some(volume) => writeln!(f, "! {} / {}: {}", failure.book, volume, failure.error)?,
+                None => writeln!(f, "! {}: {}", failure.book, failure.error)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+enum VolumeChange {
+    Added,
+    Updated(Vec<u16>),
+    Unchanged,
+}
+
+/// Brings an export directory up to date with the account.
+/// Books and volumes that weren't exported before are downloaded completely.
+/// Of already exported volumes only the pages whose source changed are downloaded again,
+/// all other pages are copied from the existing file.
+///
+/// `options.skip_existing` is ignored, the sync state decides what is downloaded.
+pub async fn sync_library(
+    session: &Session,
+    dir: &Path,
+    options: &ExportOptions,
+) -> Result<SyncReport, DigiDownloadError> {
+    tokio::fs::create_dir_all(dir).await?;
+
+    let mut state = SyncState::load(dir).await?;
+    let mut report = SyncReport::default();
+
+    for book in session.get_books().await? {
+        let volumes = match book.get_volumes().await {
+            Ok(volumes) => volumes,
+            Err(error) => {
+                report.failed.push(ExportFailure {
+                    book: book.to_string(),
+                    volume: None,
+                    error: error.into(),
+                });
+                continue;
+            }
+        };
+
+        for (index, volume) in volumes.iter().enumerate() {
+            let path = options.render_path(&book, volume, index);
+
+            match sync_volume(&mut state, dir, &path, &book, volume, options).await {
+                Ok(VolumeChange::Added) => report.added.push(path),
+                Ok(VolumeChange::Updated(pages)) => report.updated.push((path, pages)),
+                Ok(VolumeChange::Unchanged) => report.unchanged.push(path),
+                Err(error) => report.failed.push(ExportFailure {
+                    book: book.to_string(),
+                    volume: Some(volume.name().clone()),
+                    error,
+                }),
+            }
+
+            // saved after every volume so an interrupted sync doesn't have to start over
+            state.save(dir).await?;
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(volume = %volume.name(), path = %path.display())))]
+async fn sync_volume(
+    state: &mut SyncState,
+    dir: &Path,
+    path: &Path,
+    book: &Book,
+    volume: &Volume,
+    options: &ExportOptions,
+) -> Result<VolumeChange, DigiDownloadError> {
+    let scraper = volume.get_scraper().await?;
+    let page_count = scraper.fetch_page_count().await?;
+
+    let mut page_hashes = Vec::with_capacity(page_count.into());
+    for page in 1..=page_count {
+        let source = scraper.fetch_page_source(page).await?;
+        page_hashes.push(hex(&Sha256::digest(source)));
+    }
+
+    // the file may have been moved by a changed template or title, it is still the best source for unchanged pages
+    let previous = state
+        .volume(book, volume)
+        .map(|previous| (dir.join(&previous.path), previous.page_hashes.clone()));
+
+    let change = match previous {
+        Some((previous_path, previous_hashes)) if tokio::fs::try_exists(&previous_path).await? => {
+            let changed_pages: Vec<u16> = (1..=page_count)
+                .filter(|page| {
+                    let i = usize::from(*page) - 1;
+                    previous_hashes.get(i) != page_hashes.get(i)
+                })
+                .collect();
+
+            if changed_pages.is_empty() && previous_hashes.len() == page_hashes.len() {
+                let path = dir.join(path);
+                if previous_path != path {
+                    if let Some(parent) = path.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    tokio::fs::rename(&previous_path, &path).await?;
+                    move_manifest(&previous_path, &path).await?;
+                }
+                VolumeChange::Unchanged
+            } else {
+                update_volume(
+                    book,
+                    volume,
+                    scraper.as_ref(),
+                    &previous_path,
+                    &dir.join(path),
+                    &changed_pages,
+                    options,
+                )
+                .await?;
+                trace_event!(INFO, pages = ?changed_pages, "updated changed pages");
+                VolumeChange::Updated(changed_pages)
+            }
+        }
+        _ => {
+            export_volume(book, volume, &dir.join(path), options.profile()).await?;
+            VolumeChange::Added
+        }
+    };
+
+    state.set_volume(
+        book,
+        volume,
+        VolumeState {
+            path: path.to_path_buf(),
+            page_count,
+            page_hashes,
+        },
+    );
+
+    Ok(change)
+}
+
+/// Rebuilds a volume from the pages of the previous file and the newly downloaded `changed_pages`.
+/// The manifest of the previous file, if there is one, still describes the copied pages.
+async fn update_volume(
+    book: &Book,
+    volume: &Volume,
+    scraper: &dyn Scraper,
+    previous_path: &Path,
+    path: &Path,
+    changed_pages: &[u16],
+    options: &ExportOptions,
+) -> Result<(), DigiDownloadError> {
+    let previous =
+        Document::load_mem(&tokio::fs::read(previous_path).await?).map_err(ScraperError::from)?;
+    let previous_pages = previous.get_pages();
+    let previous_manifests = match Manifest::load(previous_path).await {
+        Ok(manifest) => manifest
+            .volumes()
+            .first()
+            .map(|volume| volume.pages().clone())
+            .unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let part_path = &part_path(path);
+    let page_count = scraper.fetch_page_count().await?;
+
+    let result = async {
+        let mut file = BufWriter::new(File::create(part_path).await?);
+        let mut writer = PdfWriter::new(&mut file)
+            .await
+            .map_err(ScraperError::from)?;
+        writer.start_section(1..=page_count);
+
+        let mut manifests = Vec::with_capacity(page_count.into());
+        let mut outline = BookOutline::new(None, &scraper.get_chapters());
+
+        for page in 1..=page_count {
+            let document = match previous_pages.get(&u32::from(page)) {
+                Some(page_id) if !changed_pages.contains(&page) => {
+                    manifests.push(
+                        previous_manifests
+                            .get(usize::from(page) - 1)
+                            .cloned()
+                            .unwrap_or_else(|| {
+                                PageManifest::without_source(page, scraper.get_page_url(page))
+                            }),
+                    );
+                    // its links point at the pages of the previous file
+                    let mut document = extract_page(&previous, *page_id);
+                    unresolve_destinations(&mut document);
+                    document
+                }
+                _ => {
+                    let fetched = scraper.fetch_page(page, &options.profile()).await?;
+                    manifests.push(PageManifest::new(&fetched, None));
+                    fetched.into_pdf()
+                }
+            };
+
+            let index = writer
+                .add_document(document)
+                .await
+                .map_err(ScraperError::from)?;
+            outline.add_page(&mut writer, page, index);
+        }
+
+        writer.finish().await.map_err(ScraperError::from)?;
+        Ok::<_, DigiDownloadError>(manifests)
+    }
+    .await;
+    drop(previous);
+
+    if result.is_err() {
+        // an incomplete file is useless, the error is what matters
+        let _ = tokio::fs::remove_file(part_path).await;
+    }
+    let manifests = result?;
+
+    tokio::fs::rename(part_path, path).await?;
+    if previous_path != path {
+        tokio::fs::remove_file(previous_path).await?;
+        let _ = tokio::fs::remove_file(Manifest::path(previous_path)).await;
+    }
+
+    let manifest = VolumeManifest::new(page_count, manifests).with_volume(volume);
+    book.save_manifest(path, vec![manifest]).await?;
+    Ok(())
+}
+
+/// Moves the manifest of a moved file along with it, if there is one.
+async fn move_manifest(previous_path: &Path, path: &Path) -> Result<(), std::io::Error> {
+    match tokio::fs::rename(Manifest::path(previous_path), Manifest::path(path)).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 /// Builds pattern lazily.
 #[macro_export]
 macro_rules! regex_builder {
@@ -24,3 +26,105 @@ macro_rules! regex {
         })
     }};
 }
+
+/// Emits a `tracing` event at the given level, compiled out without the `tracing` feature.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! trace_event {
+    ( $level:ident, $($arg:tt)+ ) => {
+        #[cfg(feature = "tracing")]
+        tracing::event!(tracing::Level::$level, $($arg)+);
+    };
+}
+
+/// Makes a name safe to use as a file or directory name on all common file systems.
+pub fn sanitize_file_name(name: &str) -> String {
+    let sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    // Windows doesn't allow names ending in a dot or space
+    let sanitized = sanitized.trim().trim_end_matches('.');
+
+    match sanitized {
+        "" => "_".to_string(),
+        sanitized => sanitized.to_string(),
+    }
+}
+
+/// Lowercase hexadecimal, e.g. of a hash.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Formats a time as ISO 8601 in UTC, e.g. `2024-03-01T12:30:00.123Z`.
+pub(crate) fn format_date_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds % 86_400 / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Converts days since 1970-01-01 to a date of the proleptic Gregorian calendar.
+/// See <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_replaces_reserved_characters() {
+        assert_eq!(
+            sanitize_file_name(r#"a/b\c:d*e?f"g<h>i|j"#),
+            "a_b_c_d_e_f_g_h_i_j"
+        );
+        assert_eq!(sanitize_file_name("tab\there"), "tab_here");
+    }
+
+    #[test]
+    fn sanitize_trims_trailing_dots_and_spaces() {
+        assert_eq!(sanitize_file_name("  Band 1. "), "Band 1");
+        assert_eq!(sanitize_file_name(".."), "_");
+        assert_eq!(sanitize_file_name(""), "_");
+    }
+
+    #[test]
+    fn converts_days_to_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19_783), (2024, 3, 1));
+    }
+
+    #[test]
+    fn formats_date_times_as_iso_8601() {
+        let time = UNIX_EPOCH + std::time::Duration::from_millis(1_709_296_200_123);
+        assert_eq!(format_date_time(time), "2024-03-01T12:30:00.123Z");
+        assert_eq!(format_date_time(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+}